@@ -0,0 +1,420 @@
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use writium::brotli::CompressorWriter;
+use writium::flate2::Compression;
+use writium::flate2::write::GzEncoder;
+
+use writium::resource::{deduce_type_by_ext, load_resource};
+use writium::settings::CONFIGS;
+
+/// Content coding of compressed responses.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+}
+impl Encoding {
+    /// Name used in `Accept-Encoding` and `Content-Encoding`.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+    /// Extension of precompressed files.
+    fn extension(&self) -> &'static str {
+        match *self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gz",
+        }
+    }
+}
+/// Supported encodings, preferred ones first.
+const ENCODINGS: [Encoding; 2] = [Encoding::Brotli, Encoding::Gzip];
+
+/// Compressed variant of a page or material.
+#[derive(Clone)]
+enum Variant {
+    /// Variant kept in memory.
+    Loaded(Arc<Vec<u8>>),
+    /// Variant read from file only when it's used, so that variants not
+    /// accepted by client are never read.
+    File(PathBuf),
+}
+
+/// Variants of a page or material compressed ahead of time. Variants are
+/// shared, so that pages kept in memory are not copied on every request.
+#[derive(Clone, Default)]
+pub struct Precompressed {
+    brotli: Option<Variant>,
+    gzip: Option<Variant>,
+}
+impl Precompressed {
+    fn variant(&self, encoding: Encoding) -> &Option<Variant> {
+        match encoding {
+            Encoding::Brotli => &self.brotli,
+            Encoding::Gzip => &self.gzip,
+        }
+    }
+    fn set(&mut self, encoding: Encoding, variant: Option<Variant>) {
+        match encoding {
+            Encoding::Brotli => self.brotli = variant,
+            Encoding::Gzip => self.gzip = variant,
+        }
+    }
+    /// Get the variant compressed with $encoding. It's read from file if it's
+    /// not in memory.
+    pub fn get(&self, encoding: Encoding) -> Option<Arc<Vec<u8>>> {
+        match *self.variant(encoding) {
+            Some(Variant::Loaded(ref data)) => Some(data.clone()),
+            Some(Variant::File(ref path)) => load_resource(path).map(Arc::new),
+            None => None,
+        }
+    }
+    /// Read all variants into memory.
+    pub fn load(self) -> Precompressed {
+        let mut rv = Precompressed::default();
+        for encoding in &ENCODINGS {
+            rv.set(*encoding, self.get(*encoding).map(Variant::Loaded));
+        }
+        rv
+    }
+    pub fn is_empty(&self) -> bool {
+        self.brotli.is_none() && self.gzip.is_none()
+    }
+    /// Total size of variants in memory in bytes.
+    pub fn size(&self) -> usize {
+        ENCODINGS.iter()
+            .map(|encoding| match *self.variant(*encoding) {
+                Some(Variant::Loaded(ref data)) => data.len(),
+                _ => 0,
+            })
+            .sum()
+    }
+}
+
+/// Check if data of the given media type and size should be compressed.
+/// Parameters like `charset` are ignored.
+pub fn is_compressible(media_type: &str, size: usize) -> bool {
+    if size < CONFIGS.compress_min_size { return false; }
+    let essence = media_type.split(';').next().unwrap_or_default().trim();
+    CONFIGS.compress_media_types.iter()
+        .any(|mt| mt.eq_ignore_ascii_case(essence))
+}
+
+/// Compress data with $encoding. Data compressed ahead of time are compressed
+/// at the best level, while those compressed on request are compressed
+/// faster.
+pub fn compress(data: &[u8], encoding: Encoding, best: bool)
+    -> Option<Vec<u8>> {
+    match encoding {
+        Encoding::Brotli => {
+            let mut rv = Vec::new();
+            {
+                let quality = if best { 11 } else { 5 };
+                let mut writer =
+                    CompressorWriter::new(&mut rv, 4096, quality, 22);
+                writer.write_all(data).ok()?;
+            }
+            Some(rv)
+        },
+        Encoding::Gzip => {
+            let level = if best {
+                Compression::best()
+            } else {
+                Compression::default()
+            };
+            let mut encoder = GzEncoder::new(Vec::new(), level);
+            encoder.write_all(data).ok()?;
+            encoder.finish().ok()
+        },
+    }
+}
+
+/// Path to file compressed with $encoding. It's $path with the extension of
+/// encoding appended, like `index_1.writiumcache.gz`.
+fn variant_path(path: &Path, encoding: Encoding) -> PathBuf {
+    let mut rv = path.as_os_str().to_owned();
+    rv.push(".");
+    rv.push(encoding.extension());
+    PathBuf::from(rv)
+}
+
+/// Write compressed variants of data next to $path. Variants of data that
+/// shouldn't be compressed are removed, so that stale ones are never served.
+pub fn write_precompressed(path: &Path, media_type: &str, data: &[u8]) {
+    if !is_compressible(media_type, data.len()) {
+        remove_precompressed(path);
+        return;
+    }
+    for encoding in &ENCODINGS {
+        let variant = variant_path(path, *encoding);
+        // Variants are written to temporary files first, so that partially
+        // written ones are never served.
+        let mut temp = variant.as_os_str().to_owned();
+        temp.push(".tmp");
+        let temp = PathBuf::from(temp);
+        let written = compress(data, *encoding, true)
+            .map_or(false, |compressed| File::create(&temp)
+                .and_then(|mut file| file.write_all(&compressed))
+                .and_then(|_| fs::rename(&temp, &variant))
+                .is_ok());
+        if !written {
+            error!("Unable to precompress: {}", variant.to_string_lossy());
+            let _ = fs::remove_file(&temp);
+            let _ = fs::remove_file(&variant);
+        }
+    }
+}
+/// Remove compressed variants next to $path.
+pub fn remove_precompressed(path: &Path) {
+    for encoding in &ENCODINGS {
+        let _ = fs::remove_file(variant_path(path, *encoding));
+    }
+}
+/// Find compressed variants next to $path. Variants older than $source are
+/// ignored. They are read when they are used.
+pub fn load_precompressed(path: &Path, source: &Path) -> Precompressed {
+    let mut rv = Precompressed::default();
+    for encoding in &ENCODINGS {
+        let variant = variant_path(path, *encoding);
+        if is_newer(&variant, source) {
+            rv.set(*encoding, Some(Variant::File(variant)));
+        }
+    }
+    rv
+}
+/// Check if $path is modified no earlier than $source.
+fn is_newer(path: &Path, source: &Path) -> bool {
+    let modified = |path: &Path| fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok();
+    match (modified(path), modified(source)) {
+        (Some(modified), Some(source_modified)) => modified >= source_modified,
+        _ => false,
+    }
+}
+
+/// Path where compressed variants of static file are kept, if it's in one of
+/// the published directories.
+fn material_cache_path(local_path: &Path) -> Option<PathBuf> {
+    let dirs = [
+        ("post", &CONFIGS.post_dir),
+        ("static", &CONFIGS.static_dir),
+        ("root", &CONFIGS.root_dir),
+    ];
+    dirs.iter()
+        .filter_map(|&(name, dir)| local_path.strip_prefix(dir).ok()
+            .map(|rel| path_buf![&CONFIGS.cache_dir, "material", name, rel]))
+        .next()
+}
+/// Load compressed variants of static file made by `gen_material_cache()`.
+/// Variants older than the file are ignored.
+pub fn load_precompressed_material(local_path: &Path) -> Precompressed {
+    material_cache_path(local_path)
+        .map(|path| load_precompressed(&path, local_path))
+        .unwrap_or_default()
+}
+lazy_static! {
+    /// Held while static files are being compressed, so that only one pass
+    /// runs at a time.
+    static ref PRECOMPRESSING: Mutex<()> = Mutex::new(());
+}
+/// Compress static files ahead of time in background, so that requests are
+/// not blocked by compression at the best level. Files whose variants are
+/// newer than themselves are skipped. Variants are used as soon as they are
+/// written.
+pub fn gen_material_cache() {
+    if CONFIGS.compress_media_types.is_empty() { return; }
+    let spawned = thread::Builder::new()
+        .name("precompress".to_owned())
+        .spawn(precompress_materials);
+    if spawned.is_err() {
+        error!("Unable to start precompressing static files.");
+    }
+}
+/// Compress static files in directories served to clients.
+fn precompress_materials() {
+    /// Compress static files in $dir recursively. Directories in $visited
+    /// are skipped, so that symbolic links never lead to endless loops.
+    fn precompress_dir(dir: &Path, visited: &mut HashSet<PathBuf>) {
+        match dir.canonicalize() {
+            Ok(dir) => if !visited.insert(dir) { return; },
+            Err(_) => return,
+        }
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        for path in entries.filter_map(|entry| entry.ok()).map(|en| en.path()) {
+            if path.is_dir() {
+                precompress_dir(&path, visited);
+            } else {
+                precompress_material(&path);
+            }
+        }
+    }
+    let _guard = match PRECOMPRESSING.lock() {
+        Ok(guard) => guard,
+        Err(_) => {
+            error!("Unable to lock for precompression.");
+            return;
+        },
+    };
+    info!("Precompressing static files.");
+    let mut visited = HashSet::new();
+    // Directories consumed by Writium itself are skipped, even if they are in
+    // the root directory.
+    let skipped = [&CONFIGS.template_dir, &CONFIGS.error_dir,
+        &CONFIGS.cache_dir];
+    for dir in &skipped {
+        if let Ok(dir) = Path::new(dir).canonicalize() {
+            visited.insert(dir);
+        }
+    }
+    for dir in &[&CONFIGS.post_dir, &CONFIGS.static_dir, &CONFIGS.root_dir] {
+        precompress_dir(Path::new(dir), &mut visited);
+    }
+    info!("Precompressed static files.");
+}
+/// Compress a single static file ahead of time. Files not served, or not to be
+/// compressed, are skipped without being read.
+fn precompress_material(local_path: &Path) {
+    // Only files of known types are served.
+    let media_type = match deduce_type_by_ext(local_path) {
+        Some(media_type) => media_type,
+        None => return,
+    };
+    let size = match fs::metadata(local_path) {
+        Ok(meta) => meta.len() as usize,
+        Err(_) => return,
+    };
+    if !is_compressible(media_type, size) { return; }
+    let cache_path = match material_cache_path(local_path) {
+        Some(cache_path) => cache_path,
+        None => return,
+    };
+    let up_to_date = ENCODINGS.iter()
+        .all(|encoding| is_newer(&variant_path(&cache_path, *encoding),
+            local_path));
+    if up_to_date { return; }
+    let data = match load_resource(local_path) {
+        Some(data) => data,
+        None => return,
+    };
+    if let Some(parent) = cache_path.parent() {
+        if fs::create_dir_all(parent).is_err() { return; }
+    }
+    debug!("Precompressing static file: {}", local_path.to_string_lossy());
+    write_precompressed(&cache_path, media_type, &data);
+}
+
+/// Parse `Accept-Encoding` and list supported encodings acceptable to client,
+/// the most preferred first. Encodings of the same quality are ordered by
+/// `ENCODINGS`.
+pub fn accepted_encodings(accept_encoding: &str) -> Vec<Encoding> {
+    // Quality of each coding given by client, like `gzip;q=0.8`.
+    let qualities = accept_encoding.split(',')
+        .filter_map(|item| {
+            let mut params = item.split(';');
+            let coding = params.next()?.trim().to_lowercase();
+            if coding.is_empty() { return None; }
+            let quality = params
+                .filter_map(|param| {
+                    let mut key_n_val = param.splitn(2, '=');
+                    match (key_n_val.next(), key_n_val.next()) {
+                        (Some(k), Some(v)) if k.trim() == "q" =>
+                            v.trim().parse::<f32>().ok(),
+                        _ => None,
+                    }
+                })
+                .next()
+                .unwrap_or(1.0);
+            Some((coding, quality))
+        })
+        .collect::<Vec<_>>();
+    let quality_of = |coding: &str| qualities.iter()
+        .find(|&&(ref cd, _)| cd == coding)
+        .map(|&(_, quality)| quality);
+    let mut rv = ENCODINGS.iter()
+        .filter_map(|encoding| {
+            // Codings not mentioned are accepted as `*`.
+            let quality = quality_of(encoding.name())
+                .or_else(|| quality_of("*"))
+                .unwrap_or(0.0);
+            if quality > 0.0 { Some((*encoding, quality)) } else { None }
+        })
+        .collect::<Vec<_>>();
+    // Sorting is stable, so the order of `ENCODINGS` is kept for ties.
+    rv.sort_by(|&(_, a), &(_, b)| b.partial_cmp(&a).unwrap());
+    rv.into_iter().map(|(encoding, _)| encoding).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::io::Read;
+
+    use writium::brotli::Decompressor;
+    use writium::flate2::read::GzDecoder;
+
+    use super::*;
+    use super::Encoding::*;
+
+    #[test]
+    fn accept_encoding() {
+        assert_eq!(accepted_encodings("gzip, deflate, br"), vec![Brotli, Gzip]);
+        assert_eq!(accepted_encodings("gzip;q=1.0, br;q=0.5"),
+            vec![Gzip, Brotli]);
+        assert_eq!(accepted_encodings("GZIP ; q=0.3"), vec![Gzip]);
+        // Codings not mentioned are accepted as `*`.
+        assert_eq!(accepted_encodings("br;q=0, *"), vec![Gzip]);
+        assert_eq!(accepted_encodings("*;q=0.5, gzip"), vec![Gzip, Brotli]);
+        assert_eq!(accepted_encodings("identity"), vec![]);
+        assert_eq!(accepted_encodings(""), vec![]);
+        // Invalid quality values are ignored.
+        assert_eq!(accepted_encodings("br;q=x"), vec![Brotli]);
+    }
+    #[test]
+    fn round_trip() {
+        let data = b"hello hello hello hello world".repeat(50);
+        for best in &[true, false] {
+            let gzip = compress(&data, Gzip, *best).unwrap();
+            let mut out = Vec::new();
+            GzDecoder::new(&gzip[..]).read_to_end(&mut out).unwrap();
+            assert_eq!(out, data);
+            let brotli = compress(&data, Brotli, *best).unwrap();
+            let mut out = Vec::new();
+            Decompressor::new(&brotli[..], 4096).read_to_end(&mut out)
+                .unwrap();
+            assert_eq!(out, data);
+        }
+    }
+    #[test]
+    fn lazy_variants() {
+        let dir = env::temp_dir().join("writium-test-precompressed");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("page.writiumcache");
+        File::create(&path).unwrap().write_all(b"page").unwrap();
+        File::create(variant_path(&path, Gzip)).unwrap()
+            .write_all(b"gzip").unwrap();
+        let precompressed = load_precompressed(&path, &path);
+        assert!(!precompressed.is_empty());
+        // Variants are not read until they are used.
+        assert_eq!(precompressed.size(), 0);
+        assert!(precompressed.get(Brotli).is_none());
+        assert_eq!(*precompressed.get(Gzip).unwrap(), b"gzip".to_vec());
+        let loaded = precompressed.load();
+        assert_eq!(loaded.size(), 4);
+        // Loaded variants are kept after files are removed.
+        remove_precompressed(&path);
+        assert_eq!(*loaded.get(Gzip).unwrap(), b"gzip".to_vec());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
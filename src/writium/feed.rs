@@ -0,0 +1,333 @@
+use writium::chrono::{DateTime, Utc};
+
+use writium::json::JsonValue;
+use writium::markdown;
+use writium::url::Url;
+
+use writium::compress::Precompressed;
+use writium::resource::{get_article_title_content_markdown, get_template_vars,
+    load_cached_page, parse_date_time, CachedArticles, Resource};
+use writium::settings::CONFIGS;
+use writium::taxonomy::{get_terms, TaxonomyKind};
+use writium::template::encode_url_component;
+
+/// Format of feed.
+#[derive(Clone, Copy)]
+pub enum FeedKind {
+    /// RSS 2.0, served at `/feed.xml`.
+    Rss,
+    /// Atom, served at `/atom.xml`.
+    Atom,
+    /// JSON Feed 1.1, served at `/feed.json`.
+    Json,
+}
+impl FeedKind {
+    pub fn cache_name(&self) -> &'static str {
+        match *self {
+            FeedKind::Rss => "feed_rss",
+            FeedKind::Atom => "feed_atom",
+            FeedKind::Json => "feed_json",
+        }
+    }
+    fn path(&self) -> &'static str {
+        match *self {
+            FeedKind::Rss => "/feed.xml",
+            FeedKind::Atom => "/atom.xml",
+            FeedKind::Json => "/feed.json",
+        }
+    }
+    pub fn media_type(&self) -> &'static str {
+        match *self {
+            FeedKind::Rss => "application/rss+xml",
+            FeedKind::Atom => "application/atom+xml",
+            FeedKind::Json => "application/feed+json",
+        }
+    }
+}
+
+/// Article included in feeds.
+pub struct FeedItem {
+    /// Absolute URL to article.
+    pub url: String,
+    pub title: String,
+    pub author: String,
+    pub published: DateTime<Utc>,
+    pub modified: Option<DateTime<Utc>>,
+    /// Rendered HTML of full content or digest, depending on settings.
+    pub content: String,
+    pub tags: Vec<String>,
+}
+
+/// Collect latest listed articles to be included in feeds.
+pub fn collect_feed_items(cached: &CachedArticles) -> Vec<FeedItem> {
+    cached.iter().rev()
+        .take(CONFIGS.feed_item_count as usize)
+        .filter_map(|(published, name)| {
+            let article_path = path_buf![&CONFIGS.post_dir, name];
+            let vars = get_template_vars(&article_path);
+            let (title, content) =
+                get_article_title_content_markdown(&article_path)?;
+            // Digest is the first line of content.
+            let content = if CONFIGS.feed_full_content {
+                content.as_str()
+            } else {
+                content.lines().next().unwrap_or_default()
+            };
+            Some(FeedItem {
+                url: format!("{}/post/{}/", &CONFIGS.site_url,
+                    encode_url_component(name)),
+                title: title,
+                author: vars.get("author")
+                    .map(|author| author.to_string())
+                    .unwrap_or_default(),
+                published: *published,
+                modified: parse_date_time(&vars, "modified"),
+                content: markdown::to_html(content),
+                tags: get_terms(vars.get(TaxonomyKind::Tag.var_name())),
+            })
+        })
+        .collect()
+}
+
+/// Escape text so that it can be safely inserted into XML text or quoted
+/// attribute values. Characters not allowed in XML 1.0 are removed.
+pub fn escape_xml(text: &str) -> String {
+    let mut rv = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => rv.push_str("&amp;"),
+            '<' => rv.push_str("&lt;"),
+            '>' => rv.push_str("&gt;"),
+            '"' => rv.push_str("&quot;"),
+            '\'' => rv.push_str("&apos;"),
+            '\t' | '\n' | '\r' => rv.push(ch),
+            '\u{0}'..='\u{1f}' | '\u{fffe}' | '\u{ffff}' => {},
+            _ => rv.push(ch),
+        }
+    }
+    rv
+}
+
+/// Find attribute $attr, like `href=`, inside a tag in $html.
+fn find_attr(html: &str, attr: &str) -> Option<usize> {
+    let mut from = 0;
+    while let Some(pos) = html[from..].find(attr) {
+        let pos = from + pos;
+        let before = &html[..pos];
+        if before.ends_with(char::is_whitespace) &&
+            before.rfind('<') > before.rfind('>') {
+            return Some(pos);
+        }
+        from = pos + attr.len();
+    }
+    None
+}
+/// Make relative links and image sources in $html absolute, resolving them
+/// against $base. Feed readers don't know where the content comes from, so
+/// relative ones are broken there. Absolute URLs are kept as is.
+fn absolutize_links(html: &str, base: &str) -> String {
+    let base = match Url::parse(base) {
+        Ok(base) => base,
+        Err(_) => return html.to_owned(),
+    };
+    let mut rv = String::with_capacity(html.len());
+    let mut rest = html;
+    loop {
+        let start = ["href=", "src="].iter()
+            .filter_map(|attr| find_attr(rest, attr)
+                .map(|pos| pos + attr.len()))
+            .min();
+        let start = match start {
+            Some(start) => start,
+            None => break,
+        };
+        let (before, after) = rest.split_at(start);
+        rv.push_str(before);
+        rest = after;
+        let quote = match after.chars().next() {
+            Some(quote) if quote == '"' || quote == '\'' => quote,
+            _ => continue,
+        };
+        let end = match after[1..].find(quote) {
+            Some(end) => end + 1,
+            None => break,
+        };
+        let link = &after[1..end];
+        rv.push(quote);
+        if Url::parse(link).is_ok() {
+            rv.push_str(link);
+        } else {
+            match base.join(link) {
+                Ok(url) => rv.push_str(url.as_str()),
+                Err(_) => rv.push_str(link),
+            }
+        }
+        rest = &after[end..];
+    }
+    rv.push_str(rest);
+    rv
+}
+
+fn gen_rss(items: &[FeedItem]) -> String {
+    let mut rv = String::new();
+    rv += "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n";
+    rv += "<rss version=\"2.0\" \
+        xmlns:atom=\"http://www.w3.org/2005/Atom\" \
+        xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n<channel>\n";
+    rv += &format!("<title>{}</title>\n", escape_xml(&CONFIGS.site_title));
+    rv += &format!("<link>{}/</link>\n", escape_xml(&CONFIGS.site_url));
+    rv += &format!("<description>{}</description>\n",
+        escape_xml(&CONFIGS.site_description));
+    rv += &format!("<atom:link href=\"{}{}\" rel=\"self\" \
+        type=\"application/rss+xml\"/>\n",
+        escape_xml(&CONFIGS.site_url), FeedKind::Rss.path());
+    rv += &format!("<lastBuildDate>{}</lastBuildDate>\n",
+        Utc::now().to_rfc2822());
+    for item in items {
+        rv += "<item>\n";
+        rv += &format!("<title>{}</title>\n", escape_xml(&item.title));
+        rv += &format!("<link>{}</link>\n", escape_xml(&item.url));
+        rv += &format!("<guid isPermaLink=\"true\">{}</guid>\n",
+            escape_xml(&item.url));
+        rv += &format!("<dc:creator>{}</dc:creator>\n",
+            escape_xml(&item.author));
+        rv += &format!("<pubDate>{}</pubDate>\n", item.published.to_rfc2822());
+        rv += &format!("<description>{}</description>\n",
+            escape_xml(&absolutize_links(&item.content, &item.url)));
+        rv += "</item>\n";
+    }
+    rv += "</channel>\n</rss>\n";
+    rv
+}
+
+fn gen_atom(items: &[FeedItem]) -> String {
+    let updated = items.iter()
+        .map(|item| item.modified.unwrap_or(item.published))
+        .max()
+        .unwrap_or_else(Utc::now);
+    let content_tag = if CONFIGS.feed_full_content {
+        "content"
+    } else {
+        "summary"
+    };
+    let mut rv = String::new();
+    rv += "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n";
+    rv += "<feed xmlns=\"http://www.w3.org/2005/Atom\">\n";
+    rv += &format!("<title>{}</title>\n", escape_xml(&CONFIGS.site_title));
+    if !CONFIGS.site_description.is_empty() {
+        rv += &format!("<subtitle>{}</subtitle>\n",
+            escape_xml(&CONFIGS.site_description));
+    }
+    rv += &format!("<link href=\"{}/\"/>\n", escape_xml(&CONFIGS.site_url));
+    rv += &format!("<link href=\"{}{}\" rel=\"self\"/>\n",
+        escape_xml(&CONFIGS.site_url), FeedKind::Atom.path());
+    rv += &format!("<id>{}/</id>\n", escape_xml(&CONFIGS.site_url));
+    rv += &format!("<updated>{}</updated>\n", updated.to_rfc3339());
+    for item in items {
+        // Relative links in content are resolved against the article.
+        rv += &format!("<entry xml:base=\"{}\">\n", escape_xml(&item.url));
+        rv += &format!("<title>{}</title>\n", escape_xml(&item.title));
+        rv += &format!("<link href=\"{}\"/>\n", escape_xml(&item.url));
+        rv += &format!("<id>{}</id>\n", escape_xml(&item.url));
+        rv += &format!("<author><name>{}</name></author>\n",
+            escape_xml(&item.author));
+        rv += &format!("<published>{}</published>\n",
+            item.published.to_rfc3339());
+        rv += &format!("<updated>{}</updated>\n",
+            item.modified.unwrap_or(item.published).to_rfc3339());
+        rv += &format!("<{0} type=\"html\">{1}</{0}>\n",
+            content_tag, escape_xml(&item.content));
+        rv += "</entry>\n";
+    }
+    rv += "</feed>\n";
+    rv
+}
+
+fn gen_json_feed(items: &[FeedItem]) -> String {
+    let mut feed = JsonValue::new_object();
+    feed["version"] = "https://jsonfeed.org/version/1.1".into();
+    feed["title"] = CONFIGS.site_title.as_str().into();
+    feed["home_page_url"] = format!("{}/", &CONFIGS.site_url).into();
+    feed["feed_url"] =
+        format!("{}{}", &CONFIGS.site_url, FeedKind::Json.path()).into();
+    if !CONFIGS.site_description.is_empty() {
+        feed["description"] = CONFIGS.site_description.as_str().into();
+    }
+    feed["items"] = JsonValue::new_array();
+    for item in items {
+        let mut author = JsonValue::new_object();
+        author["name"] = item.author.as_str().into();
+        let mut obj = JsonValue::new_object();
+        obj["id"] = item.url.as_str().into();
+        obj["url"] = item.url.as_str().into();
+        obj["title"] = item.title.as_str().into();
+        obj["content_html"] =
+            absolutize_links(&item.content, &item.url).into();
+        obj["date_published"] = item.published.to_rfc3339().into();
+        if let Some(modified) = item.modified {
+            obj["date_modified"] = modified.to_rfc3339().into();
+        }
+        obj["authors"] = vec![author].into();
+        obj["tags"] = item.tags.clone().into();
+        let _ = feed["items"].push(obj);
+    }
+    feed.pretty(2)
+}
+
+pub fn gen_feed(cached: &CachedArticles, kind: FeedKind) -> String {
+    let items = collect_feed_items(cached);
+    match kind {
+        FeedKind::Rss => gen_rss(&items),
+        FeedKind::Atom => gen_atom(&items),
+        FeedKind::Json => gen_json_feed(&items),
+    }
+}
+
+/// Get feed of listed articles.
+pub fn get_feed(cached: &CachedArticles, kind: FeedKind) -> Option<Resource> {
+    let (content, precompressed) = match load_cached_page(kind.cache_name()) {
+        Some(Resource::Article { content, precompressed }) => {
+            info!("Found cache. Use cached feed instead.");
+            (content, precompressed)
+        },
+        _ => {
+            warn!("Cache not found. Generate feed now.");
+            (gen_feed(cached, kind), Precompressed::default())
+        },
+    };
+    Some(Resource::Material {
+        media_type: kind.media_type().to_owned(),
+        data: content.into_bytes(),
+        precompressed: precompressed,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape() {
+        assert_eq!(escape_xml("a<b>&\"'\u{1}\tc"),
+            "a&lt;b&gt;&amp;&quot;&apos;\tc");
+    }
+    #[test]
+    fn absolute_links() {
+        let base = "https://example.com/post/a/";
+        let html = "<p><a href=\"b.html\">b</a> href=\"c\" \
+            <img alt=\"\" src='img/d.png'></p>";
+        assert_eq!(absolutize_links(html, base),
+            "<p><a href=\"https://example.com/post/a/b.html\">b</a> \
+            href=\"c\" <img alt=\"\" \
+            src='https://example.com/post/a/img/d.png'></p>");
+        assert_eq!(absolutize_links("<a href=\"/tags/\">", base),
+            "<a href=\"https://example.com/tags/\">");
+        assert_eq!(absolutize_links("<a href=\"#x\">", base),
+            "<a href=\"https://example.com/post/a/#x\">");
+        assert_eq!(absolutize_links("<a href=\"mailto:a@b.c\">", base),
+            "<a href=\"mailto:a@b.c\">");
+        assert_eq!(absolutize_links("<a href=\"http://x.org\">", base),
+            "<a href=\"http://x.org\">");
+        assert_eq!(absolutize_links("<a href=\"x", base), "<a href=\"x");
+    }
+}
@@ -0,0 +1,174 @@
+use std::collections::BTreeMap;
+
+use writium::toml;
+use writium::yaml_rust::{Yaml, YamlLoader};
+
+use writium::template::Value;
+
+/// Variables defined in front matter.
+pub type FrontMatter = BTreeMap<String, Value>;
+
+//
+// Value conversion.
+//
+
+fn from_yaml(yaml: &Yaml) -> Value {
+    match *yaml {
+        Yaml::Boolean(b) => Value::Bool(b),
+        Yaml::Integer(num) => Value::Number(num as f64),
+        Yaml::Real(_) => Value::Number(yaml.as_f64().unwrap_or_default()),
+        Yaml::String(ref st) => Value::from(st.as_str()),
+        Yaml::Array(ref arr) => Value::List(arr.iter().map(from_yaml).collect()),
+        Yaml::Hash(ref hash) => Value::Object(hash.iter()
+            // Only string keys are accessible in templates.
+            .filter_map(|(key, val)| key.as_str()
+                .map(|key| (key.to_owned(), from_yaml(val))))
+            .collect()),
+        // Null, alias and bad value.
+        _ => Value::from(""),
+    }
+}
+fn from_toml(toml: &toml::Value) -> Value {
+    match *toml {
+        toml::Value::Boolean(b) => Value::Bool(b),
+        toml::Value::Integer(num) => Value::Number(num as f64),
+        toml::Value::Float(num) => Value::Number(num),
+        toml::Value::String(ref st) => Value::from(st.as_str()),
+        toml::Value::Datetime(ref dt) => Value::from(dt.to_string()),
+        toml::Value::Array(ref arr) =>
+            Value::List(arr.iter().map(from_toml).collect()),
+        toml::Value::Table(ref table) => Value::Object(table.iter()
+            .map(|(key, val)| (key.to_owned(), from_toml(val)))
+            .collect()),
+    }
+}
+
+//
+// Front matter parsing.
+//
+
+fn parse_yaml(text: &str) -> Option<FrontMatter> {
+    let docs = match YamlLoader::load_from_str(text) {
+        Ok(docs) => docs,
+        Err(err) => {
+            warn!("Unable to parse YAML front matter: {}", err);
+            return None;
+        },
+    };
+    match docs.into_iter().next() {
+        Some(ref doc) => match from_yaml(doc) {
+            Value::Object(obj) => Some(obj),
+            _ => {
+                warn!("YAML front matter is not a mapping.");
+                None
+            },
+        },
+        // Empty front matter.
+        None => Some(FrontMatter::new()),
+    }
+}
+fn parse_toml(text: &str) -> Option<FrontMatter> {
+    match text.parse::<toml::Value>() {
+        Ok(ref table) => match from_toml(table) {
+            Value::Object(obj) => Some(obj),
+            _ => None,
+        },
+        Err(err) => {
+            warn!("Unable to parse TOML front matter: {}", err);
+            None
+        },
+    }
+}
+
+/// Split front matter from the beginning of article. Front matter is enclosed
+/// by lines of `---` for YAML, or `+++` for TOML. The parsed front matter is
+/// returned along with the rest of article. If the front matter cannot be
+/// parsed, it's still removed from the article, but None is returned in place
+/// of it.
+pub fn split_front_matter(text: &str) -> (Option<FrontMatter>, &str) {
+    let delim = if text.starts_with("---") {
+        "---"
+    } else if text.starts_with("+++") {
+        "+++"
+    } else {
+        return (None, text);
+    };
+    // The opening line must contain nothing but the delimiter.
+    let mut lines = text.split('\n');
+    if lines.next().map(|line| line.trim_end()) != Some(delim) {
+        return (None, text);
+    }
+    let body_beg = text.find('\n').map_or(text.len(), |pos| pos + 1);
+    let mut pos = body_beg;
+    for line in lines {
+        let line_end = pos + line.len();
+        if line.trim_end() == delim {
+            let front_matter = &text[body_beg..pos];
+            let rest = text.get((line_end + 1)..).unwrap_or_default();
+            let parsed = if delim == "---" {
+                parse_yaml(front_matter)
+            } else {
+                parse_toml(front_matter)
+            };
+            return (parsed, rest);
+        }
+        pos = line_end + 1;
+    }
+    // Front matter is not closed. Treat it as normal content.
+    (None, text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn yaml() {
+        let (front_matter, rest) = split_front_matter("---\r\n\
+            title: Hi <x>\r\ntags: [a, b]\r\ndraft: true\r\nn: 1.5\r\n\
+            ---\r\n# Body\r\n");
+        let front_matter = front_matter.unwrap();
+        assert_eq!(front_matter["title"].as_text(), Some("Hi <x>"));
+        assert_eq!(front_matter["tags"].to_string(), "a, b");
+        assert_eq!(front_matter["draft"].to_string(), "true");
+        assert_eq!(front_matter["n"].to_string(), "1.5");
+        assert_eq!(rest, "# Body\r\n");
+    }
+    #[test]
+    fn toml() {
+        let (front_matter, rest) = split_front_matter("+++\ntitle = \"T\"\n\
+            published = 2017-09-01T00:00:00Z\n[series]\nname = \"S\"\n+++\n\
+            body");
+        let front_matter = front_matter.unwrap();
+        assert_eq!(front_matter["title"].as_text(), Some("T"));
+        assert_eq!(front_matter["published"].to_string(),
+            "2017-09-01T00:00:00Z");
+        assert_eq!(front_matter["series"].field("name").unwrap().to_string(),
+            "S");
+        assert_eq!(rest, "body");
+    }
+    #[test]
+    fn empty_and_closed_at_end() {
+        let (front_matter, rest) = split_front_matter("---\n---");
+        assert!(front_matter.unwrap().is_empty());
+        assert_eq!(rest, "");
+    }
+    #[test]
+    fn not_front_matter() {
+        for text in &["# Title\n---\n", "---\nunclosed", "----\na: 1\n----\n",
+            "--- x\na: 1\n---\n"] {
+            let (front_matter, rest) = split_front_matter(text);
+            assert!(front_matter.is_none());
+            assert_eq!(rest, *text);
+        }
+    }
+    #[test]
+    fn invalid_front_matter_is_removed() {
+        let (front_matter, rest) = split_front_matter("---\n- a\n---\nbody");
+        assert!(front_matter.is_none());
+        assert_eq!(rest, "body");
+        let (front_matter, rest) = split_front_matter("+++\n= x\n+++\nbody");
+        assert!(front_matter.is_none());
+        assert_eq!(rest, "body");
+    }
+}
@@ -0,0 +1,152 @@
+use std::collections::BTreeMap;
+use std::fs::{metadata, File};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
+
+use writium::json::JsonValue;
+
+use writium::resource::{load_json_object, load_resource};
+use writium::settings::CONFIGS;
+
+/// State of a cached page when it was generated.
+#[derive(Clone)]
+pub struct ManifestEntry {
+    /// Fingerprint of all the sources the page is generated from.
+    pub fingerprint: String,
+    /// Publish time of article in RFC 3339, if it's indexed.
+    pub published: Option<String>,
+}
+
+/// Record of cached pages and their sources. Pages are generated again only
+/// if their sources have changed since last time.
+pub struct Manifest {
+    entries: BTreeMap<String, ManifestEntry>,
+}
+impl Manifest {
+    fn path() -> PathBuf {
+        path_buf![&CONFIGS.cache_dir, "manifest.json"]
+    }
+    /// Load manifest from cache directory. An empty manifest is returned if
+    /// it doesn't exist or cannot be parsed.
+    pub fn load() -> Manifest {
+        let mut entries = BTreeMap::new();
+        if let Some(obj) = load_json_object(&Manifest::path()) {
+            for (key, val) in obj.iter() {
+                if let Some(fingerprint) = val["fingerprint"].as_str() {
+                    entries.insert(key.to_owned(), ManifestEntry {
+                        fingerprint: fingerprint.to_owned(),
+                        published: val["published"].as_str()
+                            .map(str::to_owned),
+                    });
+                }
+            }
+        } else {
+            info!("Cache manifest not found. All pages will be generated.");
+        }
+        Manifest { entries: entries }
+    }
+    /// Save manifest to cache directory.
+    pub fn save(&self) {
+        let mut obj = JsonValue::new_object();
+        for (key, entry) in &self.entries {
+            let mut val = JsonValue::new_object();
+            val["fingerprint"] = entry.fingerprint.as_str().into();
+            if let Some(ref published) = entry.published {
+                val["published"] = published.as_str().into();
+            }
+            obj[key.as_str()] = val;
+        }
+        match File::create(&Manifest::path()) {
+            Ok(mut file) => if file.write_all(obj.dump().as_bytes()).is_err() {
+                error!("Failed to write cache manifest.");
+            },
+            Err(_) => error!("Unable to create cache manifest."),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&ManifestEntry> {
+        self.entries.get(key)
+    }
+    pub fn insert(&mut self, key: String, entry: ManifestEntry) {
+        self.entries.insert(key, entry);
+    }
+    pub fn remove(&mut self, key: &str) -> Option<ManifestEntry> {
+        self.entries.remove(key)
+    }
+    pub fn keys(&self) -> Vec<String> {
+        self.entries.keys().cloned().collect()
+    }
+}
+
+/// 64-bit FNV-1a hasher. Fingerprints are kept across runs, so the algorithm
+/// must not change with the Rust version, unlike `DefaultHasher`.
+struct FnvHasher(u64);
+impl FnvHasher {
+    fn new() -> FnvHasher {
+        FnvHasher(0xcbf2_9ce4_8422_2325)
+    }
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    /// Write a field prefixed with its length, so that fields are never
+    /// mistaken for each other.
+    fn write_field(&mut self, bytes: &[u8]) {
+        self.write(format!("{}:", bytes.len()).as_bytes());
+        self.write(bytes);
+    }
+}
+
+/// Fingerprint files by their paths, modification times and contents, along
+/// with $extra, which carries states other than files. Missing files are
+/// fingerprinted as well, so that their creation can be noticed.
+pub fn fingerprint(paths: &[PathBuf], extra: &str) -> String {
+    let mut hasher = FnvHasher::new();
+    for path in paths {
+        hasher.write_field(path.to_string_lossy().as_bytes());
+        // Modification time is checked as well because it's exposed to
+        // templates as `modified`.
+        let modified = metadata(path)
+            .and_then(|meta| meta.modified())
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|dur| format!("{}.{:09}", dur.as_secs(), dur.subsec_nanos()))
+            .unwrap_or_default();
+        hasher.write_field(modified.as_bytes());
+        // Missing files differ from empty ones.
+        match load_resource(path) {
+            Some(data) => hasher.write_field(&data),
+            None => hasher.write(b"-"),
+        }
+    }
+    hasher.write_field(extra.as_bytes());
+    format!("{:016x}", hasher.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fnv() {
+        let hash = |bytes: &[u8]| {
+            let mut hasher = FnvHasher::new();
+            hasher.write(bytes);
+            hasher.0
+        };
+        assert_eq!(hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(hash(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+    #[test]
+    fn fingerprint_is_stable() {
+        assert_eq!(fingerprint(&[], "extra"), fingerprint(&[], "extra"));
+        assert!(fingerprint(&[], "a") != fingerprint(&[], "b"));
+        // Paths are fingerprinted even if files are missing.
+        let missing = [PathBuf::from("/nonexistent/writium")];
+        assert!(fingerprint(&missing, "") != fingerprint(&[], ""));
+    }
+}
@@ -1,14 +1,23 @@
-pub use super::chrono;
+use super::chrono;
+extern crate brotli;
+extern crate flate2;
 extern crate iron;
 extern crate json;
 extern crate getopts;
 extern crate hyper_native_tls;
 extern crate markdown;
+extern crate notify;
+extern crate toml;
 extern crate url;
+extern crate yaml_rust;
 
 use std::io;
 use std::path::Path;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use self::chrono::Utc;
 
 use self::iron::prelude::*;
 use self::iron::method::Method;
@@ -16,24 +25,44 @@ use self::iron::status;
 
 use self::hyper_native_tls::NativeTlsServer;
 
+use self::url::percent_encoding::percent_decode;
+
+mod compress;
+mod feed;
+mod front_matter;
+mod manifest;
+mod page_cache;
 mod resource;
 mod response_gen;
+mod sitemap;
+mod taxonomy;
 mod template;
+mod watcher;
 
 pub mod settings;
 
 use self::settings::CONFIGS;
 use self::resource::Resource;
 use self::resource::Resource::*;
-use self::response_gen::{gen_error, gen_error_page, gen_page, gen_spec,
-    gen_redirection};
+use self::compress::{accepted_encodings, Encoding};
+use self::feed::FeedKind;
+use self::page_cache::{CachedPage, PageCache};
+use self::taxonomy::{Taxonomy, TaxonomyKind};
+use self::response_gen::{gen_cached, gen_error, gen_error_page, gen_page,
+    gen_spec, gen_redirection, gen_template_error_page};
 
-fn resource_to_response(path: &str, resource: Option<Resource>) -> Response {
+fn resource_to_response(path: &str, resource: Option<Resource>,
+    accepted: &[Encoding]) -> Response {
     match resource {
         Some(rsc) => match rsc {
-            Article { content } => gen_page(content),
+            Article { content, precompressed } => gen_page(
+                Arc::new(content.into_bytes()), &precompressed, accepted),
             InvalidArticle => gen_error_page(status::NotFound),
-            Material { media_type, data } => gen_spec(data, media_type),
+            InvalidTemplate { error } => gen_template_error_page(&error),
+            Material { media_type, data, precompressed } =>
+                gen_spec(Arc::new(data), &media_type, &precompressed,
+                    accepted),
+            Cached(page) => gen_cached(&page, accepted),
             InvalidMaterial => gen_error(status::NotFound),
             AddSlash => gen_redirection(&(format!("/{}/", &path))),
         },
@@ -41,23 +70,128 @@ fn resource_to_response(path: &str, resource: Option<Resource>) -> Response {
     }
 }
 
+/// Find the value of $key in query string.
+fn get_query_param<'a>(query: Option<&'a str>, key: &str) -> Option<&'a str> {
+    query?.split('&')
+        .filter_map(|pair| {
+            let mut key_n_val = pair.splitn(2, '=');
+            match (key_n_val.next(), key_n_val.next()) {
+                (Some(k), Some(v)) if k == key => Some(v),
+                _ => None,
+            }
+        })
+        .next()
+}
+
+/// Get page number in query string. 0 is returned if it's absent or invalid.
+fn get_page(query: Option<&str>) -> u32 {
+    get_query_param(query, "page")
+        .and_then(|pg| pg.parse::<u32>().ok())
+        .unwrap_or(0)
+}
+
+/// Compare secrets in time independent of where they differ, so that they
+/// cannot be guessed byte by byte from response time.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() &&
+        a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+/// Check if drafts should be shown for the request. Either preview mode is on
+/// or the correct preview token is given.
+fn is_preview(query: Option<&str>) -> bool {
+    CONFIGS.preview_mode || (!CONFIGS.preview_token.is_empty() &&
+        get_query_param(query, "preview").map_or(false, |token|
+            constant_time_eq(token.as_bytes(),
+                CONFIGS.preview_token.as_bytes())))
+}
+/// Get encodings acceptable to client, the most preferred first.
+fn get_accepted_encodings(req: &Request) -> Vec<Encoding> {
+    match req.headers.get_raw("Accept-Encoding") {
+        Some(lines) => accepted_encodings(&lines.iter()
+            .map(|line| String::from_utf8_lossy(line).into_owned())
+            .collect::<Vec<_>>()
+            .join(",")),
+        None => Vec::new(),
+    }
+}
+
 /// Shared data object carrying all the informations might be used to make
 /// response.
 struct WritiumServer {
     /// Map of listed articles sorted by publish time.
     cached_articles: resource::CachedArticles,
+    /// Map of articles to be published in the future.
+    scheduled_articles: resource::CachedArticles,
+    /// Listed articles grouped by tags and categories.
+    taxonomy: Taxonomy,
+    /// Rendered pages kept in memory.
+    page_cache: Mutex<PageCache>,
 }
 impl WritiumServer {
+    /// Update cache. Only pages whose sources have changed are generated
+    /// again.
+    fn recache(&mut self) {
+        let (cached, scheduled, taxonomy) = resource::gen_cache();
+        self.taxonomy = taxonomy;
+        self.cached_articles = cached;
+        self.scheduled_articles = scheduled;
+        self.clear_page_cache();
+    }
+    /// Remove all pages kept in memory.
+    fn clear_page_cache(&self) {
+        match self.page_cache.lock() {
+            Ok(mut locked) => (*locked).clear(),
+            Err(_) => error!("Unable to lock page cache."),
+        }
+    }
+    /// Get page from memory if it's there. Otherwise, get it with $get, and
+    /// keep it in memory for later use.
+    fn get_cached<F>(&self, key: String, get: F) -> Option<Resource>
+        where F: FnOnce() -> Option<Resource> {
+        match self.page_cache.lock() {
+            Ok(mut locked) => {
+                if !(*locked).is_enabled() { return get(); }
+                if let Some(page) = (*locked).get(&key) {
+                    debug!("Found page in memory: {}", &key);
+                    return Some(Cached(page));
+                }
+            },
+            Err(_) => {
+                error!("Unable to lock page cache.");
+                return get();
+            },
+        }
+        // Don't keep the lock while the page is being loaded.
+        match get().map(CachedPage::from_resource) {
+            Some(Ok(page)) => {
+                let page = Arc::new(page);
+                if let Ok(mut locked) = self.page_cache.lock() {
+                    (*locked).insert(key, page.clone());
+                }
+                Some(Cached(page))
+            },
+            Some(Err(resource)) => Some(resource),
+            None => None,
+        }
+    }
     /// Make response for non-root directories. Only `./post` is allowed to
     /// store articles. Requests for articles out of it will be responded with
     /// 404.
     fn make_response_for_dir(&self, local_dir: String, path: String,
-        in_post_dir: bool) -> Response {
+        in_post_dir: bool, preview: bool, accepted: &[Encoding]) -> Response {
         // Access to directory-root is not allowed.
         if path.is_empty() { return gen_error_page(status::Forbidden); }
         let local_path = path_buf![&local_dir, &path];
+        // Single-file articles don't have directories, so check their
+        // Markdown files instead.
+        let checked_path = if in_post_dir && !local_path.exists() {
+            resource::locate_article_content(&local_path)
+                .unwrap_or_else(|| local_path.clone())
+        } else {
+            local_path.clone()
+        };
         // Make sure requested file is under published directory.
-        match local_path.canonicalize() {
+        match checked_path.canonicalize() {
             // Canonicalize $local_dir because the annoying prefix `\\?\` on
             // Windows.
             Ok(buf) => if !buf.starts_with(
@@ -73,37 +207,35 @@ impl WritiumServer {
                 return gen_error_page(status::NotFound);
             }
         }
+        // Only articles are kept in memory. Previews are never kept.
+        let get = || resource::get_resource(&local_path, in_post_dir, preview);
         resource_to_response(
             &path,
-            resource::get_resource(local_path.as_path(), in_post_dir)
+            if in_post_dir && !preview && path.ends_with('/') {
+                self.get_cached(format!("post/{}", &path), get)
+            } else {
+                get()
+            },
+            accepted
         )
     }    
     /// Make response for root directory.
     fn make_response_for_root(&self, path: String,
-        query: Option<&str>) -> Response {
+        query: Option<&str>, accepted: &[Encoding]) -> Response {
         if path.is_empty() {
             // Index page.
             info!("Request for index.");
+            let page = get_page(query).max(1);
+            // Don't generate or cache pages that don't exist.
+            if page > resource::count_pages(&self.cached_articles) {
+                return gen_error_page(status::NotFound);
+            }
             resource_to_response(
                 &path,
-                if let Some(q) = query {
-                    let mut page: u32 = 0;
-                    for pair in q.split('&') {
-                        let mut key_n_val = pair.split('=');
-                        let key = key_n_val.next();
-                        let val = key_n_val.next();
-                        if key.is_some() && key.unwrap() == "page" &&
-                            val.is_some() {
-                            if let Ok(pg) = val.unwrap().parse::<u32>() {
-                                page = pg;
-                            }
-                            break;
-                        }
-                    }
+                self.get_cached(format!("index_{}", page), || {
                     resource::get_index_page(&self.cached_articles, page)
-                } else {
-                    resource::get_index_page(&self.cached_articles, 0)
-                }
+                }),
+                accepted
             )
         } else {
             let local_path = path_buf![&CONFIGS.root_dir, &path];
@@ -114,11 +246,87 @@ impl WritiumServer {
                     resource::deduce_type_by_ext(&local_path) {
                     resource::get_material(&local_path, media_type)
                 } else {
-                    resource::get_article(&local_path)
-                }
+                    resource::get_article(&local_path, is_preview(query))
+                },
+                accepted
             )
         }
     }
+    /// Make response for listing pages of terms, like `/tag/<name>/`.
+    fn make_response_for_term(&self, kind: TaxonomyKind, path: &[&str],
+        query: Option<&str>, accepted: &[Encoding]) -> Response {
+        // $path[0] is the URL prefix of taxonomy.
+        match path.len() {
+            2 => return resource_to_response(
+                &path.join("/"), Some(AddSlash), accepted),
+            3 if path[2].is_empty() => {},
+            _ => return gen_error_page(status::NotFound),
+        }
+        let term = match percent_decode(path[1].as_bytes()).decode_utf8() {
+            Ok(term) => term,
+            Err(_) => return gen_error_page(status::NotFound),
+        };
+        info!("Request for {} '{}'.", kind.url_prefix(), &term);
+        let page = get_page(query).max(1);
+        let page_count = self.taxonomy.get(kind, &term)
+            .map_or(0, resource::count_pages);
+        if page > page_count { return gen_error_page(status::NotFound); }
+        resource_to_response(
+            "",
+            {
+                let key = format!("{}/{}/{}", kind.url_prefix(), &term, page);
+                self.get_cached(key, || {
+                    resource::get_term_page(&self.taxonomy, kind, &term, page)
+                })
+            },
+            accepted
+        )
+    }
+    /// Make response for overview of terms at `/tags/`.
+    fn make_response_for_terms(&self, path: &[&str], accepted: &[Encoding])
+        -> Response {
+        match path.len() {
+            1 => resource_to_response(
+                &path.join("/"), Some(AddSlash), accepted),
+            2 if path[1].is_empty() => resource_to_response(
+                "",
+                self.get_cached("tags".to_owned(),
+                    || resource::get_terms_page(&self.taxonomy)),
+                accepted
+            ),
+            _ => gen_error_page(status::NotFound),
+        }
+    }
+    /// Make response for archive pages, like `/archive/2017/09/`.
+    fn make_response_for_archive(&self, path: &[&str], accepted: &[Encoding])
+        -> Response {
+        // $path[0] is `archive`. The last element is empty if the path ends
+        // with a slash.
+        if path.len() < 2 || !path[path.len() - 1].is_empty() {
+            return resource_to_response(
+                &path.join("/"), Some(AddSlash), accepted);
+        }
+        let period = &path[1..(path.len() - 1)];
+        if period.len() > 2 { return gen_error_page(status::NotFound); }
+        let year = match period.get(0).map(|year| year.parse::<i32>()) {
+            Some(Ok(year)) => Some(year),
+            Some(Err(_)) => return gen_error_page(status::NotFound),
+            None => None,
+        };
+        let month = match period.get(1).map(|month| month.parse::<u32>()) {
+            Some(Ok(month)) => Some(month),
+            Some(Err(_)) => return gen_error_page(status::NotFound),
+            None => None,
+        };
+        info!("Request for archive.");
+        resource_to_response(
+            "",
+            self.get_cached(path.join("/"), || {
+                resource::get_archive_page(&self.cached_articles, year, month)
+            }),
+            accepted
+        )
+    }
     /// Response to incoming requests.
     fn make_response(&self, req: &Request) -> Response {
         /// Map search directory to local storage directory.
@@ -143,19 +351,65 @@ impl WritiumServer {
         // Assign different search directory for different root. If the requested
         // thing doesn't exist, ignore with 404 returned.
         let search_dir = path.get(0).unwrap().to_owned();
+        let accepted = &get_accepted_encodings(req);
+        // Generated listing pages.
+        match search_dir {
+            "tag" => return self.make_response_for_term(
+                TaxonomyKind::Tag, &path, req.url.query(), accepted),
+            "category" => return self.make_response_for_term(
+                TaxonomyKind::Category, &path, req.url.query(), accepted),
+            "tags" => return self.make_response_for_terms(&path, accepted),
+            "archive" =>
+                return self.make_response_for_archive(&path, accepted),
+            "feed.xml" if path.len() == 1 => return resource_to_response(
+                "",
+                self.get_cached(search_dir.to_owned(), || {
+                    feed::get_feed(&self.cached_articles, FeedKind::Rss)
+                }),
+                accepted
+            ),
+            "atom.xml" if path.len() == 1 => return resource_to_response(
+                "",
+                self.get_cached(search_dir.to_owned(), || {
+                    feed::get_feed(&self.cached_articles, FeedKind::Atom)
+                }),
+                accepted
+            ),
+            "feed.json" if path.len() == 1 => return resource_to_response(
+                "",
+                self.get_cached(search_dir.to_owned(), || {
+                    feed::get_feed(&self.cached_articles, FeedKind::Json)
+                }),
+                accepted
+            ),
+            "sitemap.xml" if path.len() == 1 => return resource_to_response(
+                "",
+                self.get_cached(search_dir.to_owned(),
+                    || sitemap::get_sitemap(&self.cached_articles,
+                        &self.taxonomy)),
+                accepted
+            ),
+            "robots.txt" if path.len() == 1 =>
+                return resource_to_response(
+                    "", sitemap::get_robots(), accepted),
+            _ => {},
+        }
         // Read data from storage.
         match map_search_dir(&search_dir) {
             Some(dir) => {
                 self.make_response_for_dir(
                     dir.to_owned(),
                     path[1..].join("/"),
-                    search_dir == "post"
+                    search_dir == "post",
+                    is_preview(req.url.query()),
+                    accepted
                 )
             },
             None => {
                 self.make_response_for_root(
                     path.join("/"),
-                    req.url.query()
+                    req.url.query(),
+                    accepted
                 )
             },
         }
@@ -167,6 +421,41 @@ impl WritiumServer {
     }
 }
 
+/// Longest interval between checks for scheduled articles. Scheduled articles
+/// can be changed by `recache`, so they have to be checked once in a while.
+const MAX_PUBLISH_INTERVAL: u64 = 60;
+
+/// Publish scheduled articles in background when their time comes.
+fn spawn_publisher(shared: Arc<RwLock<WritiumServer>>) {
+    thread::spawn(move || loop {
+        let next = match shared.read() {
+            Ok(locked) => (*locked).scheduled_articles.keys().next().cloned(),
+            Err(_) => {
+                error!("Unable to read-lock.");
+                return;
+            },
+        };
+        let max_wait = Duration::from_secs(MAX_PUBLISH_INTERVAL);
+        let wait = match next {
+            // Negative duration means it's time to publish.
+            Some(dt) => (dt - Utc::now()).to_std()
+                .unwrap_or(Duration::from_secs(0))
+                .min(max_wait),
+            None => max_wait,
+        };
+        thread::sleep(wait);
+        if let Ok(mut locked) = shared.write() {
+            let server = &mut *locked;
+            if resource::publish_scheduled(&mut server.cached_articles,
+                &mut server.scheduled_articles, &mut server.taxonomy) {
+                server.clear_page_cache();
+            }
+        } else {
+            error!("Unable to write-lock.");
+        }
+    });
+}
+
 /// Writium controller.
 pub struct Writium {
     shared: Arc<RwLock<WritiumServer>>,
@@ -176,9 +465,17 @@ pub struct Writium {
 impl Writium {
     pub fn new() -> Writium {
         // Use Rwlock to ensure there is no read / write conflicts
+        let (cached, scheduled, taxonomy) = resource::gen_cache();
         let shared = Arc::new(RwLock::new(WritiumServer {
-            cached_articles: resource::gen_cache(),
+            taxonomy: taxonomy,
+            cached_articles: cached,
+            scheduled_articles: scheduled,
+            page_cache: Mutex::new(PageCache::new(CONFIGS.memory_cache_size)),
         }));
+        spawn_publisher(shared.clone());
+        if CONFIGS.watch {
+            watcher::spawn_watcher(shared.clone());
+        }
         let shared_remote = shared.clone();
         let handler = move |req: &mut Request| {
             if let Ok(locked) = shared_remote.read() {
@@ -243,11 +540,36 @@ impl Writium {
                 self.close();
                 return true;
             },
-            "remove_cache" => resource::remove_cache(),
+            "remove_cache" => {
+                resource::remove_cache();
+                match self.shared.read() {
+                    Ok(locked) => (*locked).clear_page_cache(),
+                    Err(_) => error!("Unable to read-lock."),
+                }
+            },
+            "cache_stats" => match self.shared.read() {
+                Ok(locked) => match (*locked).page_cache.lock() {
+                    Ok(page_cache) => {
+                        let stats = page_cache.stats();
+                        info!("Page cache: {} pages, {} / {} bytes; \
+                            {} hits, {} misses; \
+                            {} evictions, {} bytes evicted.",
+                            page_cache.len(), page_cache.size(),
+                            page_cache.capacity(), stats.hits, stats.misses,
+                            stats.evictions, stats.evicted_bytes);
+                    },
+                    Err(_) => error!("Unable to lock page cache."),
+                },
+                Err(_) => error!("Unable to read-lock."),
+            },
             "recache" => {
                 if let Ok(mut locked) = self.shared.write() {
-                    resource::remove_cache();
-                    (*locked).cached_articles = resource::gen_cache();
+                    // Only changed pages are generated again unless a full
+                    // recache is requested.
+                    if args.get(0) == Some(&"full") {
+                        resource::remove_cache();
+                    }
+                    (*locked).recache();
                 } else {
                     error!("Unable to write-lock.");
                 }
@@ -276,7 +598,24 @@ impl Writium {
 }
 impl Drop for Writium {
     fn drop(&mut self) {
-        resource::remove_cache();
+        // Kept cache is used to warm start next time.
+        if !CONFIGS.keep_cache {
+            resource::remove_cache();
+        }
         self.close();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compare_secrets() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+        assert!(!constant_time_eq(b"", b"s"));
+        assert!(constant_time_eq(b"", b""));
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use writium::compress::Precompressed;
+use writium::resource::Resource;
+
+/// Rendered page kept in memory. Pages are shared with responses, so that
+/// they are not copied on every request.
+pub struct CachedPage {
+    /// Media type of page. None for HTML pages.
+    pub media_type: Option<String>,
+    pub data: Arc<Vec<u8>>,
+    pub precompressed: Precompressed,
+}
+impl CachedPage {
+    /// Make cached page from generated resource. Only pages and materials can
+    /// be cached. Other resources are given back. Compressed variants are
+    /// kept in memory as well.
+    pub fn from_resource(resource: Resource) -> Result<CachedPage, Resource> {
+        match resource {
+            Resource::Article { content, precompressed } => Ok(CachedPage {
+                media_type: None,
+                data: Arc::new(content.into_bytes()),
+                precompressed: precompressed.load(),
+            }),
+            Resource::Material { media_type, data, precompressed } =>
+                Ok(CachedPage {
+                    media_type: Some(media_type),
+                    data: Arc::new(data),
+                    precompressed: precompressed.load(),
+                }),
+            resource => Err(resource),
+        }
+    }
+}
+
+/// Statistics of page cache.
+#[derive(Clone, Copy, Default)]
+pub struct PageCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub evicted_bytes: u64,
+}
+
+/// In-memory cache of rendered pages, bounded by total size in bytes. The
+/// least recently used pages are evicted first.
+pub struct PageCache {
+    capacity: usize,
+    size: usize,
+    /// Incremented on every access, to order pages by their last use.
+    tick: u64,
+    /// Pages indexed by key, along with the tick they were last used.
+    pages: HashMap<String, (u64, Arc<CachedPage>)>,
+    /// Keys of pages ordered by the tick they were last used.
+    usage: BTreeMap<u64, String>,
+    stats: PageCacheStats,
+}
+impl PageCache {
+    /// Make a page cache holding at most $capacity bytes. Page cache of zero
+    /// capacity is disabled.
+    pub fn new(capacity: usize) -> PageCache {
+        PageCache {
+            capacity: capacity,
+            size: 0,
+            tick: 0,
+            pages: HashMap::new(),
+            usage: BTreeMap::new(),
+            stats: PageCacheStats::default(),
+        }
+    }
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+    /// Size of page in bytes, including its key and compressed variants.
+    fn page_size(key: &str, page: &CachedPage) -> usize {
+        key.len() + page.data.len() + page.precompressed.size() +
+            page.media_type.as_ref().map_or(0, |mt| mt.len())
+    }
+
+    /// Get page and mark it as the most recently used.
+    pub fn get(&mut self, key: &str) -> Option<Arc<CachedPage>> {
+        self.tick += 1;
+        let tick = self.tick;
+        match self.pages.get_mut(key) {
+            Some(&mut (ref mut last_used, ref page)) => {
+                self.usage.remove(last_used);
+                self.usage.insert(tick, key.to_owned());
+                *last_used = tick;
+                self.stats.hits += 1;
+                Some(page.clone())
+            },
+            None => {
+                self.stats.misses += 1;
+                None
+            },
+        }
+    }
+    /// Put page into cache. The least recently used pages are evicted if
+    /// there isn't enough space. Pages larger than the capacity are not
+    /// cached.
+    pub fn insert(&mut self, key: String, page: Arc<CachedPage>) {
+        let page_size = PageCache::page_size(&key, &page);
+        if page_size > self.capacity { return; }
+        self.remove(&key);
+        while self.size + page_size > self.capacity {
+            let oldest = match self.usage.keys().next() {
+                Some(&tick) => tick,
+                None => break,
+            };
+            if let Some(key) = self.usage.remove(&oldest) {
+                if let Some((_, page)) = self.pages.remove(&key) {
+                    let evicted = PageCache::page_size(&key, &page);
+                    debug!("Evicted page from memory: {}", &key);
+                    self.size -= evicted;
+                    self.stats.evictions += 1;
+                    self.stats.evicted_bytes += evicted as u64;
+                }
+            }
+        }
+        self.tick += 1;
+        self.size += page_size;
+        self.usage.insert(self.tick, key.clone());
+        self.pages.insert(key, (self.tick, page));
+    }
+    fn remove(&mut self, key: &str) {
+        if let Some((last_used, page)) = self.pages.remove(key) {
+            self.usage.remove(&last_used);
+            self.size -= PageCache::page_size(key, &page);
+        }
+    }
+    /// Remove all pages. Statistics are kept.
+    pub fn clear(&mut self) {
+        self.pages.clear();
+        self.usage.clear();
+        self.size = 0;
+    }
+
+    /// Total size of cached pages in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+    pub fn len(&self) -> usize {
+        self.pages.len()
+    }
+    pub fn stats(&self) -> PageCacheStats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(media_type: Option<&str>, size: usize) -> Arc<CachedPage> {
+        Arc::new(CachedPage {
+            media_type: media_type.map(str::to_owned),
+            data: Arc::new(vec![0; size]),
+            precompressed: Precompressed::default(),
+        })
+    }
+
+    #[test]
+    fn eviction() {
+        let mut cache = PageCache::new(30);
+        // Each page takes 10 bytes along with its 1-byte key.
+        cache.insert("a".to_owned(), page(None, 9));
+        cache.insert("b".to_owned(), page(None, 9));
+        cache.insert("c".to_owned(), page(None, 9));
+        assert!(cache.get("a").is_some());
+        // `b` is the least recently used now.
+        cache.insert("d".to_owned(), page(None, 9));
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("c").is_some());
+        assert!(cache.get("d").is_some());
+        let stats = cache.stats();
+        assert_eq!(stats.evictions, 1);
+        assert_eq!(stats.evicted_bytes, 10);
+        assert_eq!(stats.hits, 4);
+        assert_eq!(stats.misses, 1);
+        // Pages larger than the capacity are not cached.
+        cache.insert("e".to_owned(), page(None, 30));
+        assert!(cache.get("e").is_none());
+        assert_eq!(cache.len(), 3);
+    }
+    #[test]
+    fn shared_pages() {
+        let mut cache = PageCache::new(30);
+        let cached = page(None, 9);
+        cache.insert("a".to_owned(), cached.clone());
+        let got = cache.get("a").unwrap();
+        assert!(Arc::ptr_eq(&cached.data, &got.data));
+    }
+    #[test]
+    fn byte_accounting() {
+        let mut cache = PageCache::new(100);
+        assert_eq!(cache.size(), 0);
+        // Key and media type are counted as well.
+        cache.insert("ab".to_owned(), page(Some("text/css"), 10));
+        assert_eq!(cache.size(), 20);
+        cache.insert("c".to_owned(), page(None, 9));
+        assert_eq!(cache.size(), 30);
+        // Replacing a page releases the space of the old one.
+        cache.insert("ab".to_owned(), page(None, 3));
+        assert_eq!(cache.size(), 15);
+        assert_eq!(cache.len(), 2);
+        cache.clear();
+        assert_eq!(cache.size(), 0);
+        assert_eq!(cache.len(), 0);
+        assert_eq!(cache.capacity(), 100);
+    }
+}
@@ -1,11 +1,12 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::ffi::OsStr;
 use std::fs;
 use std::fs::File;
 use std::io::{Read, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use writium::chrono::{DateTime, Utc};
+use writium::chrono::{Datelike, DateTime, Utc};
 
 use writium::json;
 use writium::json::JsonValue;
@@ -13,20 +14,38 @@ use writium::json::object::Object;
 
 use writium::markdown;
 
+use writium::compress::{gen_material_cache, load_precompressed,
+    load_precompressed_material, remove_precompressed, write_precompressed,
+    Precompressed};
+use writium::feed::{gen_feed, FeedKind};
+use writium::front_matter::split_front_matter;
+use writium::manifest::{fingerprint, Manifest, ManifestEntry};
+use writium::page_cache::CachedPage;
 use writium::settings::CONFIGS;
-use writium::template::TemplateVariables;
+use writium::sitemap::{gen_sitemap, SITEMAP_MEDIA_TYPE};
+use writium::taxonomy::{term_path, Taxonomy, TaxonomyKind};
+use writium::template::{collect_dependencies, encode_url_component,
+    load_template, TemplateError, TemplateVariables, Value};
 
 pub enum Resource {
     Material {
         media_type: String,
         data: Vec<u8>,
+        precompressed: Precompressed,
     },
     InvalidMaterial,
     Article {
-        content: String
+        content: String,
+        precompressed: Precompressed,
     },
     InvalidArticle,
+    /// Page cannot be generated because of a broken template.
+    InvalidTemplate {
+        error: TemplateError,
+    },
     AddSlash,
+    /// Page kept in memory.
+    Cached(Arc<CachedPage>),
 }
 
 //
@@ -90,60 +109,129 @@ pub fn ensure_dir(dir_path: &Path) {
 // Article utilities.
 //
 
-/// Get template variables from `metadata.json` and filesystem metadata.
-fn get_template_vars(local_path: &Path) -> TemplateVariables {
+/// Publication status of article, given by variable `status`. Case and
+/// surrounding spaces are ignored.
+#[derive(Clone, Copy, PartialEq)]
+pub enum ArticleStatus {
+    /// Served and listed. It's the default.
+    Published,
+    /// Served by URL, but not listed in index, feeds or sitemap.
+    Unlisted,
+    /// Never served, unless in preview.
+    Draft,
+}
+impl ArticleStatus {
+    pub fn from_vars(vars: &TemplateVariables) -> ArticleStatus {
+        let status = vars.get("status").and_then(Value::as_text)
+            .map(|status| status.trim().to_lowercase());
+        match status.as_ref().map(String::as_str) {
+            Some("draft") => ArticleStatus::Draft,
+            Some("unlisted") => ArticleStatus::Unlisted,
+            _ => ArticleStatus::Published,
+        }
+    }
+}
+
+/// Parse time variable in RFC 3339.
+pub fn parse_date_time(vars: &TemplateVariables, key: &str)
+    -> Option<DateTime<Utc>> {
+    let pd = vars.get(key).and_then(Value::as_text)?;
+    DateTime::parse_from_rfc3339(pd).ok()
+        .map(|parsed| DateTime::from_utc(parsed.naive_utc(), Utc))
+}
+/// Check if article is to be published in the future.
+fn is_scheduled(vars: &TemplateVariables) -> bool {
+    parse_date_time(vars, "published").map_or(false, |dt| dt > Utc::now())
+}
+
+/// Get template variables from `metadata.json`, front matter and filesystem
+/// metadata.
+pub fn get_template_vars(local_path: &Path) -> TemplateVariables {
     let mut vars = TemplateVariables::new();
     vars.read_from_metadata(local_path);
+    vars.read_from_front_matter(local_path);
     vars.complete_with_default(local_path);
     vars
 }
 
+/// Locate the Markdown file of article. An article is either a directory
+/// containing `content.md`, or a single Markdown file named after the article,
+/// e.g., `foo.md` for article `foo`. The directory takes precedence.
+///
+/// Some(~) will be returned if the Markdown file exists. None, otherwise.
+pub fn locate_article_content(local_path: &Path) -> Option<PathBuf> {
+    let in_dir = path_buf![local_path, "content.md"];
+    if in_dir.is_file() { return Some(in_dir); }
+    let name = local_path.file_name().and_then(OsStr::to_str)?;
+    let single_file = path_buf![local_path.parent()?, format!("{}.md", name)];
+    if single_file.is_file() { Some(single_file) } else { None }
+}
+/// Load Markdown file of article. Leading UTF-8 BOM is removed, and line
+/// endings are normalized to LF, so that articles edited on any platform can
+/// be handled in the same way.
+pub fn load_article_text(local_path: &Path) -> Option<String> {
+    let path = locate_article_content(local_path)?;
+    load_text_resource(path.as_path()).map(|s| {
+        let s = s.trim_start_matches('\u{feff}');
+        s.replace("\r\n", "\n").replace('\r', "\n")
+    })
+}
+
+/// Get title and Markdown content of article. Title is taken from front
+/// matter if it's given there; otherwise, the first line is the title.
 pub fn get_article_title_content_markdown(local_path: &Path)
     -> Option<(String, String)> {
-    let path = path_buf![local_path, "content.md"];
-    match load_text_resource(path.as_path()) {
-        Some(mut s) => {
-            let linebreak_pos = match s.find("\r\n") {
-                Some(pos) => pos,
-                None => return None,
-            };
-            // Get title.
-            let title_line: String = s.drain(..linebreak_pos).collect();
-            let mut pos = 0;
-            for (idx, ch) in title_line.char_indices() {
-                if ch != '#' { pos = idx; break; }
-            }
-            let title = title_line[pos..].trim();
-            // Get content.
-            let content = s.trim();
-            Some((title.to_owned(), content.to_owned()))
-        },
-        None => None,
+    let s = load_article_text(local_path)?;
+    let (front_matter, s) = split_front_matter(&s);
+    let title = front_matter.as_ref()
+        .and_then(|fm| fm.get("title"))
+        .and_then(Value::as_text);
+    if let Some(title) = title {
+        return Some((title.to_owned(), s.trim().to_owned()));
     }
+    // Leading empty lines are not title.
+    let s = s.trim_start();
+    if s.is_empty() { return None; }
+    // If there is only one line, it's the title and the content is empty.
+    let (title_line, content) = match s.find('\n') {
+        Some(pos) => (&s[..pos], &s[(pos + 1)..]),
+        None => (s, ""),
+    };
+    let title = title_line.trim_start_matches('#').trim();
+    Some((title.to_owned(), content.trim().to_owned()))
 }
 
 /// Generate article with provided template variables.
 fn gen_article_given_vars(local_path: &Path, vars: &mut TemplateVariables) -> Option<Resource> {
-    use self::Resource::{Article, InvalidArticle};
+    use self::Resource::{Article, InvalidArticle, InvalidTemplate};
 
-    let template_path =
-        path_buf![&CONFIGS.template_dir, &CONFIGS.post_template_path];
-    let template = match load_text_resource(template_path.as_path()) {
-        Some(tmpl) => tmpl,
-        None => return Some(InvalidArticle),
+    let template =
+        match load_template(Path::new(&CONFIGS.post_template_path)) {
+        Ok(tmpl) => tmpl,
+        Err(err) => {
+            error!("Unable to load post template: {}", err);
+            return Some(InvalidTemplate { error: err });
+        },
     };
     let (title, content) =
         match get_article_title_content_markdown(local_path) {
         Some(tp) => tp,
         None => return Some(InvalidArticle),
     };
-    vars.insert("content".to_owned(), markdown::to_html(&content));
+    vars.insert("content".to_owned(),
+        Value::Html(markdown::to_html(&content)));
     vars.insert("title".to_owned(), title);
-    let md_opt = vars.fill_template(&template);
+    let md_res = vars.fill_template(&template);
     vars.remove("content");
-    match md_opt {
-        Some(md) => Some(Article{content: md}),
-        None => Some(InvalidArticle),
+    match md_res {
+        Ok(md) => Some(Article {
+            content: md,
+            precompressed: Precompressed::default(),
+        }),
+        Err(err) => {
+            error!("Unable to fill post template: {}", err);
+            Some(InvalidTemplate { error: err })
+        },
     }
 }
 
@@ -151,48 +239,43 @@ fn gen_article_given_vars(local_path: &Path, vars: &mut TemplateVariables) -> Op
 // Index utilities.
 //
 
-/// Generate digests for a certain page.
-fn gen_digests(cached: &CachedArticles, page: u32) -> String {
-    let template_path =
-        path_buf![&CONFIGS.template_dir, &CONFIGS.digest_template_path];
-    let template = load_text_resource(&template_path)
-        .unwrap_or_default();
-
-    let mut vars = TemplateVariables::new();
-    let mut digest_collected = String::new();
-    for (_, article_name) in cached.iter().rev()
+/// Get template variables of digest of an article.
+fn get_digest_vars(article_name: &str) -> Option<TemplateVariables> {
+    let article_path = path_buf![&CONFIGS.post_dir, article_name];
+    let mut vars = get_template_vars(&article_path);
+    let (title, content) = get_article_title_content_markdown(&article_path)?;
+    // Show the first line only. Articles with no content have empty digests.
+    let content = content.lines().next().unwrap_or_default();
+    vars.insert("path".to_owned(), format!("/post/{}/", article_name));
+    vars.insert("title".to_owned(), title);
+    vars.insert("content".to_owned(), Value::Html(markdown::to_html(&content)));
+    Some(vars)
+}
+/// Collect template variables of articles shown on a certain page.
+fn collect_digest_vars(cached: &CachedArticles, page: u32)
+    -> Vec<TemplateVariables> {
+    cached.iter().rev()
         // Page number is 1-based, so minus 1.
-        .skip(((page - 1) * &CONFIGS.digests_per_page) as usize)
-        .take(CONFIGS.digests_per_page as usize) {
-        let path = path_buf![&CONFIGS.post_dir, &article_name];
-        vars.read_from_metadata(&path);
-        vars.complete_with_default(&path);
-        let article_path =
-                path_buf![&CONFIGS.post_dir, &article_name];
-        if let Some((title, mut content)) =
-            get_article_title_content_markdown(&article_path) {
-            // Show 50 characters.
-            let linebreak_pos = match content.find("\r\n") {
-                Some(pos) => pos,
-                // Only one line with no content. 
-                None => continue,
-            };
-            let content: String = content.drain(..linebreak_pos).collect();
-            vars.insert("path".to_owned(), format!("/post/{}/", &article_name));
-            vars.insert("title".to_owned(), title);
-            vars.insert("content".to_owned(), markdown::to_html(&content));
-            digest_collected += &vars.fill_template(&template)
-                .unwrap_or_default();
-        }
+        .skip((page - 1).saturating_mul(CONFIGS.digests_per_page) as usize)
+        .take(CONFIGS.digests_per_page as usize)
+        .filter_map(|(_, article_name)| get_digest_vars(article_name))
+        .collect()
+}
+/// Generate digests with template variables of each article.
+fn gen_digests(digest_vars: &[TemplateVariables])
+    -> Result<String, TemplateError> {
+    let template = load_template(Path::new(&CONFIGS.digest_template_path))?;
+    let mut digest_collected = String::new();
+    for vars in digest_vars {
+        digest_collected += &vars.fill_template(&template)?;
     }
-    digest_collected
+    Ok(digest_collected)
 }
-/// Generate pagination for certain page.
-fn gen_pagination(cached: &CachedArticles, page: u32) -> Option<String> {
-    let pagination_template_path =
-        path_buf![&CONFIGS.template_dir, &CONFIGS.pagination_template_path];
+/// Generate pagination for certain page. Links are relative to $base_path.
+fn gen_pagination(cached: &CachedArticles, base_path: &str, page: u32)
+    -> Result<String, TemplateError> {
     let pagination_template =
-        load_text_resource(&pagination_template_path).unwrap_or_default();
+        load_template(Path::new(&CONFIGS.pagination_template_path))?;
 
     /// Provide the corresponding page number or empty string depending on the
     /// existence of that page.
@@ -209,7 +292,7 @@ fn gen_pagination(cached: &CachedArticles, page: u32) -> Option<String> {
     if page_cur > 0 && page_cur <= max_page {
         vars.insert("previousPage".to_owned(), page_cur.to_string());
         vars.insert("previousPageLink".to_owned(),
-            format!("/?page={}", page_cur).to_string());
+            format!("{}?page={}", base_path, page_cur));
     }
     page_cur += 1;
     if page_cur > 0 && page_cur <= max_page {
@@ -220,152 +303,635 @@ fn gen_pagination(cached: &CachedArticles, page: u32) -> Option<String> {
      {
         vars.insert("nextPage".to_owned(), page_cur.to_string());
         vars.insert("nextPageLink".to_owned(),
-            format!("/?page={}", page_cur).to_string());
+            format!("{}?page={}", base_path, page_cur));
     }
     vars.fill_template(&pagination_template)
 }
-/// Generate given page of index with given digest. 
-fn gen_index_page_given_digest(cached: &CachedArticles, digests: String, page: u32)
-    -> Option<String> {
-    let index_template_path =
-        path_buf![&CONFIGS.template_dir, &CONFIGS.index_template_path];
-    let index_template = load_text_resource(&index_template_path)
-        .unwrap_or_default();
+/// Generate given page of a listing of articles, like index, with the given
+/// template. Variables specific to the listing are given in $vars.
+fn gen_listing_page(template_path: &str, cached: &CachedArticles,
+    base_path: &str, page: u32, mut vars: TemplateVariables)
+    -> Result<String, TemplateError> {
+    let template = load_template(Path::new(template_path))?;
 
-    let mut vars = TemplateVariables::new();
-    vars.insert("digests".to_owned(), digests);
+    let digest_vars = collect_digest_vars(cached, page);
+    vars.insert("digests".to_owned(), Value::Html(gen_digests(&digest_vars)?));
+    // Also expose raw digest variables so that digests can be composed in
+    // listing template directly.
+    vars.insert("articles".to_owned(), digest_vars.into_iter()
+        .map(Value::from)
+        .collect::<Vec<_>>());
     vars.insert("pagination".to_owned(),
-        gen_pagination(cached, page).unwrap_or_default());
-    if let Some(filled) = vars.fill_template(&index_template) {
-        Some(filled)
-    } else {
-        None
+        Value::Html(gen_pagination(cached, base_path, page)?));
+    vars.fill_template(&template)
+}
+/// Generate given page of index.
+fn gen_index_page(cached: &CachedArticles, page: u32)
+    -> Result<String, TemplateError> {
+    gen_listing_page(&CONFIGS.index_template_path, cached, "/", page,
+        TemplateVariables::new())
+}
+/// Generate given page of listing of articles of a term.
+fn gen_term_page(taxonomy: &Taxonomy, kind: TaxonomyKind, term: &str,
+    page: u32) -> Option<Result<String, TemplateError>> {
+    let cached = taxonomy.get(kind, term)?;
+    let mut vars = TemplateVariables::new();
+    vars.insert("taxonomy".to_owned(), kind.url_prefix());
+    vars.insert("term".to_owned(), term);
+    vars.insert("count".to_owned(), Value::Number(cached.len() as f64));
+    Some(gen_listing_page(&CONFIGS.taxonomy_template_path, cached,
+        &term_path(kind, term), page, vars))
+}
+/// Generate overview of all terms.
+fn gen_terms_page(taxonomy: &Taxonomy) -> Result<String, TemplateError> {
+    let template = load_template(Path::new(&CONFIGS.tags_template_path))?;
+    let mut vars = TemplateVariables::new();
+    for kind in &[TaxonomyKind::Tag, TaxonomyKind::Category] {
+        let terms = taxonomy.terms(*kind).iter()
+            .map(|(term, cached)| {
+                let mut obj = BTreeMap::new();
+                obj.insert("name".to_owned(), Value::from(term.as_str()));
+                obj.insert("path".to_owned(),
+                    Value::from(term_path(*kind, term)));
+                obj.insert("count".to_owned(),
+                    Value::Number(cached.len() as f64));
+                Value::Object(obj)
+            })
+            .collect::<Vec<_>>();
+        vars.insert(kind.var_name().to_owned(), terms);
+    }
+    vars.fill_template(&template)
+}
+
+//
+// Archive utilities.
+//
+
+/// Name of cache file of archive page, without extension.
+fn archive_cache_name(year: Option<i32>, month: Option<u32>) -> String {
+    match (year, month) {
+        (Some(year), Some(month)) => format!("archive_{}_{:02}", year, month),
+        (Some(year), None) => format!("archive_{}", year),
+        _ => "archive".to_owned(),
+    }
+}
+/// Path to archive page.
+fn archive_path(year: Option<i32>, month: Option<u32>) -> String {
+    match (year, month) {
+        (Some(year), Some(month)) => format!("/archive/{}/{:02}/", year, month),
+        (Some(year), None) => format!("/archive/{}/", year),
+        _ => "/archive/".to_owned(),
+    }
+}
+/// Count listed articles by years and months.
+fn count_by_month(cached: &CachedArticles)
+    -> BTreeMap<i32, BTreeMap<u32, u32>> {
+    let mut years = BTreeMap::new();
+    for dt in cached.keys() {
+        *years.entry(dt.year())
+            .or_insert_with(BTreeMap::new)
+            .entry(dt.month())
+            .or_insert(0) += 1;
+    }
+    years
+}
+/// Collect years and months in which articles are published, newest first.
+fn collect_archive_periods(cached: &CachedArticles) -> Vec<Value> {
+    fn period(text: String, path: String, count: u32)
+        -> BTreeMap<String, Value> {
+        let mut obj = BTreeMap::new();
+        obj.insert("name".to_owned(), Value::from(text));
+        obj.insert("path".to_owned(), Value::from(path));
+        obj.insert("count".to_owned(), Value::Number(count as f64));
+        obj
+    }
+    count_by_month(cached).into_iter().rev()
+        .map(|(year, months)| {
+            let mut obj = period(year.to_string(),
+                archive_path(Some(year), None), months.values().sum());
+            obj.insert("months".to_owned(), Value::List(months.into_iter()
+                .rev()
+                .map(|(month, count)| Value::Object(period(
+                    format!("{:02}", month),
+                    archive_path(Some(year), Some(month)),
+                    count)))
+                .collect()));
+            Value::Object(obj)
+        })
+        .collect()
+}
+/// Generate archive page of the given period. The whole archive is generated
+/// if year is not given.
+///
+/// None is returned if no article is published in the period.
+fn gen_archive_page(cached: &CachedArticles, year: Option<i32>,
+    month: Option<u32>) -> Option<Result<String, TemplateError>> {
+    let in_period: Vec<_> = cached.iter().rev()
+        .filter(|&(dt, _)| year.map_or(true, |year| dt.year() == year) &&
+            month.map_or(true, |month| dt.month() == month))
+        .map(|(_, name)| name)
+        .collect();
+    if in_period.is_empty() && year.is_some() { return None; }
+    let template =
+        match load_template(Path::new(&CONFIGS.archive_template_path)) {
+        Ok(tmpl) => tmpl,
+        Err(err) => return Some(Err(err)),
+    };
+    let mut vars = TemplateVariables::new();
+    if let Some(year) = year {
+        vars.insert("year".to_owned(), year.to_string());
+    }
+    if let Some(month) = month {
+        vars.insert("month".to_owned(), format!("{:02}", month));
     }
+    vars.insert("count".to_owned(), Value::Number(in_period.len() as f64));
+    vars.insert("articles".to_owned(), in_period.into_iter()
+        .filter_map(|name| get_digest_vars(name))
+        .map(Value::from)
+        .collect::<Vec<_>>());
+    vars.insert("years".to_owned(), collect_archive_periods(cached));
+    Some(vars.fill_template(&template))
 }
 
 //
 // Cache generation.
 //
 
-/// Generate cache for articles.
-fn gen_article_cache() -> CachedArticles {
-    fn gen_single_cache(entry: &Path) -> Option<(DateTime<Utc>, String)> {
-        fn parse_date_time(vars: &TemplateVariables, key: &str) -> Option<DateTime<Utc>> {
-            if let Some(pd) = vars.get(key) {
-                if let Ok(parsed) =
-                    DateTime::parse_from_rfc3339(&pd.to_string()) {
-                    return Some(DateTime::from_utc(parsed.naive_utc(), Utc));
-                }
+/// Media type of pages rendered from templates.
+const PAGE_MEDIA_TYPE: &'static str = "text/html";
+
+/// Write generated page to cache file named $file_name in cache directory,
+/// along with its compressed variants. Cache is removed if it cannot be
+/// written completely.
+///
+/// Returns true if cache is written.
+pub fn write_page_cache(file_name: &str, media_type: &str, filled: &str)
+    -> bool {
+    let cache_path = page_cache_path(file_name);
+    match File::create(&cache_path) {
+        Ok(mut file) => {
+            match file.write_all(filled.as_bytes()) {
+                Ok(_) => info!("Generated cache: {}", file_name),
+                Err(_) => {
+                    error!("Failed to generate cache: {}", file_name);
+                    remove_cache_file(&cache_path);
+                    return false;
+                },
             }
-            None
-        }
-        // Get metadata of each directory.
-        if !entry.is_dir() { return None; }
+        },
+        Err(_) => {
+            error!("Unable to create cache file: {}", file_name);
+            remove_cache_file(&cache_path);
+            return false;
+        },
+    };
+    write_precompressed(&cache_path, media_type, filled.as_bytes());
+    true
+}
+/// Remove cache file and its compressed variants.
+fn remove_cache_file(cache_path: &Path) {
+    let _ = fs::remove_file(cache_path);
+    remove_precompressed(cache_path);
+}
 
-        let file_name = match entry.file_name().and_then(OsStr::to_str) {
-            Some(fname) => fname.to_owned(),
-            None => return None,
-        };
-        let mut cache_path =
-            path_buf![&CONFIGS.cache_dir, "post", &file_name];
-        cache_path.set_extension("writiumcache");
-
-        let article_path = path_buf![entry, ""];
-        let mut vars = get_template_vars(&article_path);
-        let filled = match gen_article_given_vars(&article_path, &mut vars) {
-            Some(Resource::Article { content }) => content,
-            _ => return None,
-        };
-        // In case there is a dot in the file name. set_extension() is
-        // not used.
-        match File::create(&cache_path) {
-            Ok(mut file) => {
-                match file.write(filled.as_bytes()) {
-                    Ok(_) => {
-                        info!("Generated cache: {}", &file_name);
-                        match parse_date_time(&vars, "published") {
-                            Some(dt) => return Some((dt, file_name)),
-                            None => warn!("...But failed to index it."),
-                        }
-                    },
-                    Err(_) => warn!("Failed to generate cache: {}", &file_name),
-                }
-            },
-            Err(_) => warn!("Unable to create cache file: {}", &file_name),
-        };
-        None
-    }
+/// Path to cache file of article.
+fn article_cache_path(name: &str) -> PathBuf {
+    let mut cache_path = path_buf![&CONFIGS.cache_dir, "post", name];
+    cache_path.set_extension("writiumcache");
+    cache_path
+}
+/// Generate cache for a single article. The publish time and name of the
+/// article are returned if it should be indexed. The cache file is removed if
+/// the article cannot be generated, so that stale pages are never served.
+fn gen_single_cache(file_name: String) -> Option<(DateTime<Utc>, String)> {
+    let cache_path = article_cache_path(&file_name);
 
+    let article_path = path_buf![&CONFIGS.post_dir, &file_name, ""];
+    let mut vars = get_template_vars(&article_path);
+    let status = ArticleStatus::from_vars(&vars);
+    if status == ArticleStatus::Draft {
+        info!("Skipped draft: {}", &file_name);
+        // It might be cached before it's turned into a draft.
+        remove_cache_file(&cache_path);
+        return None;
+    }
+    // Cache of scheduled articles is generated when they are published.
+    if is_scheduled(&vars) {
+        info!("Scheduled article: {}", &file_name);
+        remove_cache_file(&cache_path);
+        return parse_date_time(&vars, "published").map(|dt| (dt, file_name));
+    }
+    let filled = match gen_article_given_vars(&article_path, &mut vars) {
+        Some(Resource::Article { content, .. }) => content,
+        _ => {
+            warn!("Failed to generate cache: {}", &file_name);
+            remove_cache_file(&cache_path);
+            return None;
+        },
+    };
+    // In case there is a dot in the file name. set_extension() is
+    // not used.
+    match File::create(&cache_path) {
+        Ok(mut file) => {
+            match file.write_all(filled.as_bytes()) {
+                Ok(_) => {
+                    info!("Generated cache: {}", &file_name);
+                    write_precompressed(&cache_path, PAGE_MEDIA_TYPE,
+                        filled.as_bytes());
+                    if status == ArticleStatus::Unlisted {
+                        info!("...And it's unlisted.");
+                        return None;
+                    }
+                    match parse_date_time(&vars, "published") {
+                        Some(dt) => return Some((dt, file_name)),
+                        None => warn!("...But failed to index it."),
+                    }
+                },
+                Err(_) => {
+                    warn!("Failed to generate cache: {}", &file_name);
+                    remove_cache_file(&cache_path);
+                },
+            }
+        },
+        Err(_) => {
+            warn!("Unable to create cache file: {}", &file_name);
+            remove_cache_file(&cache_path);
+        },
+    };
+    None
+}
+/// Get fingerprint of sources of an article. $templates is the fingerprint of
+/// templates it depends on, which are shared by all articles.
+fn fingerprint_article(name: &str, templates: &str) -> String {
+    let article_path = path_buf![&CONFIGS.post_dir, name, ""];
+    let mut paths = Vec::new();
+    paths.push(path_buf![&article_path, "metadata.json"]);
+    paths.push(locate_article_content(&article_path)
+        .unwrap_or_else(|| path_buf![&article_path, "content.md"]));
+    fingerprint(&paths, templates)
+}
+/// Generate cache for articles whose sources have changed since last
+/// generation, according to $manifest.
+fn gen_article_cache(manifest: &mut Manifest) -> CachedArticles {
+    /// Get name of article from an entry in post directory. Markdown files
+    /// shadowed by directory articles of the same name are skipped, and so are
+    /// directories holding only assets of single-file articles.
+    fn get_article_name(entry: &Path) -> Option<String> {
+        if entry.is_dir() {
+            let name = entry.file_name().and_then(OsStr::to_str)?;
+            let is_assets = !path_buf![entry, "content.md"].is_file() &&
+                path_buf![&CONFIGS.post_dir, format!("{}.md", name)]
+                    .is_file();
+            return if is_assets { None } else { Some(name.to_owned()) };
+        }
+        if entry.extension().and_then(OsStr::to_str) != Some("md") {
+            return None;
+        }
+        let name = entry.file_stem().and_then(OsStr::to_str)?;
+        if path_buf![&CONFIGS.post_dir, name, "content.md"].is_file() {
+            warn!("Single-file article is shadowed by directory: {}", name);
+            return None;
+        }
+        Some(name.to_owned())
+    }
     info!("Generating cache for articles.");
     ensure_dir(Path::new(&CONFIGS.cache_dir));
     // Generate cache for posts.
     ensure_dir(&path_buf![&CONFIGS.cache_dir, "post"]);
     let mut map: CachedArticles = BTreeMap::new();
+    let templates = fingerprint_templates(&[&CONFIGS.post_template_path]);
+    let mut stale = manifest.keys().into_iter()
+        .filter(|key| key.starts_with("post/"))
+        .collect::<BTreeSet<_>>();
     match fs::read_dir(&CONFIGS.post_dir) {
         Ok(entries) => for entry in entries {
-            if let Ok(en) = entry {
-                if let Some((dt, name)) = gen_single_cache(&en.path()) {
+            if let Some(name) = entry.ok()
+                .and_then(|en| get_article_name(&en.path())) {
+                let key = format!("post/{}", &name);
+                stale.remove(&key);
+                let fingerprint = fingerprint_article(&name, &templates);
+                let up_to_date = manifest.get(&key)
+                    .map_or(false, |entry| entry.fingerprint == fingerprint) &&
+                    article_cache_path(&name).is_file();
+                let indexed = if up_to_date {
+                    info!("Cache is up to date: {}", &name);
+                    manifest.get(&key)
+                        .and_then(|entry| entry.published.as_ref())
+                        .and_then(|pd| DateTime::parse_from_rfc3339(pd).ok())
+                        .map(|dt| (dt.with_timezone(&Utc), name))
+                } else {
+                    let cache_path = article_cache_path(&name);
+                    let indexed = gen_single_cache(name);
+                    // Drafts, scheduled articles and those that failed to
+                    // be generated have no cache. Check them again next time.
+                    if cache_path.is_file() {
+                        manifest.insert(key, ManifestEntry {
+                            fingerprint: fingerprint,
+                            published: indexed.as_ref()
+                                .map(|&(ref dt, _)| dt.to_rfc3339()),
+                        });
+                    } else {
+                        manifest.remove(&key);
+                    }
+                    indexed
+                };
+                if let Some((dt, name)) = indexed {
                     map.insert(dt, name);
                 }
             }
         },
         _ => error!("Unable to read from post directory."),
     }
+    // Remove cache of articles that no longer exist.
+    for key in stale {
+        info!("Removing cache of removed article: {}", &key[5..]);
+        remove_cache_file(&article_cache_path(&key[5..]));
+        manifest.remove(&key);
+    }
     map
 }
-/// Generate cache for the given page of index.
-fn gen_index_page_cache(cached: &CachedArticles, page: u32) {
-    info!("Generating cache for index pages.");    
-    let file_name = format!("index_{}", page);
-    let mut cache_path =
-        path_buf![&CONFIGS.cache_dir, &file_name];
-    cache_path.set_extension("writiumcache");
+/// Number of pages needed to list the articles. There is at least 1 page.
+pub fn count_pages(cached: &CachedArticles) -> u32 {
+    let per_page = CONFIGS.digests_per_page.max(1) as usize;
+    ((cached.len() + per_page - 1) / per_page).max(1) as u32
+}
+/// Articles listed on the given page.
+fn articles_on_page(cached: &CachedArticles, page: u32) -> CachedArticles {
+    cached.iter().rev()
+        .skip((page - 1).saturating_mul(CONFIGS.digests_per_page) as usize)
+        .take(CONFIGS.digests_per_page as usize)
+        .map(|(dt, name)| (*dt, name.clone()))
+        .collect()
+}
+/// Name of cache file of listing page of term, without extension.
+fn term_cache_name(kind: TaxonomyKind, term: &str, page: u32) -> String {
+    format!("{}_{}_{}", kind.url_prefix(), encode_url_component(term), page)
+}
+/// Get fingerprint of templates, including the templates they depend on.
+fn fingerprint_templates(template_paths: &[&String]) -> String {
+    let mut paths = Vec::new();
+    for template_path in template_paths {
+        paths.extend(collect_dependencies(Path::new(template_path)));
+    }
+    fingerprint(&paths, "")
+}
 
-    let filled =
-        match gen_index_page_given_digest(cached, gen_digests(cached, page), page) {
-        Some(filed) => filed,
-        None => return,
-    };
-    match File::create(&cache_path) {
-        Ok(mut file) => {
-            match file.write(filled.as_bytes()) {
-                Ok(_) => info!("Generated cache: {}", &file_name),
-                Err(_) => error!("Failed to generate cache: {}", &file_name),
+/// Listing pages are generated again only if their sources have changed.
+/// Pages that no longer exist are removed.
+struct ListingCacheGenerator<'a> {
+    manifest: &'a mut Manifest,
+    /// Keys of pages that should exist.
+    existing: BTreeSet<String>,
+}
+impl<'a> ListingCacheGenerator<'a> {
+    fn new(manifest: &'a mut Manifest) -> ListingCacheGenerator<'a> {
+        ListingCacheGenerator {
+            manifest: manifest,
+            existing: BTreeSet::new(),
+        }
+    }
+    /// Get fingerprint of a listing page from the fingerprint of its
+    /// templates, the articles it lists, and other states in $extra.
+    fn fingerprint(&self, templates: &str, articles: &CachedArticles,
+        extra: &str) -> String {
+        let articles = articles.iter()
+            .map(|(dt, name)| format!("{} {} {}\n", dt.to_rfc3339(), name,
+                self.manifest.get(&format!("post/{}", name))
+                    .map_or("", |entry| &entry.fingerprint)))
+            .collect::<String>();
+        fingerprint(&[], &format!("{}\n{}{}", templates, articles, extra))
+    }
+    /// Generate cache for page named $name if its fingerprint has changed.
+    /// $gen gives None if the page doesn't exist.
+    fn gen<F>(&mut self, name: String, media_type: &str, fingerprint: String,
+        gen: F) where F: FnOnce() -> Option<Result<String, TemplateError>> {
+        let key = format!("page/{}", &name);
+        self.existing.insert(key.clone());
+        let up_to_date = self.manifest.get(&key)
+            .map_or(false, |entry| entry.fingerprint == fingerprint) &&
+            page_cache_path(&name).is_file();
+        if up_to_date { return; }
+        match gen() {
+            Some(Ok(filled)) => {
+                if write_page_cache(&name, media_type, &filled) {
+                    self.manifest.insert(key, ManifestEntry {
+                        fingerprint: fingerprint,
+                        published: None,
+                    });
+                } else {
+                    self.manifest.remove(&key);
+                }
+            },
+            Some(Err(err)) => {
+                error!("Unable to generate page {}: {}", &name, err);
+                // Don't serve the stale page.
+                remove_cache_file(&page_cache_path(&name));
+                self.manifest.remove(&key);
+            },
+            None => { self.existing.remove(&key); },
+        }
+    }
+    /// Remove cache of pages that no longer exist.
+    fn remove_stale(self) {
+        for key in self.manifest.keys() {
+            if key.starts_with("page/") && !self.existing.contains(&key) {
+                info!("Removing cache of removed page: {}", &key[5..]);
+                remove_cache_file(&page_cache_path(&key[5..]));
+                self.manifest.remove(&key);
             }
-        },
-        Err(_) => error!("Unable to create cache file: {}", &file_name),
-    };
+        }
+    }
 }
 
-/// Generate cache for all articles and the fist page of index.
-pub fn gen_cache() -> CachedArticles {
-    let cached = gen_article_cache();
-    gen_index_page_cache(&cached, 1);
-    cached
+/// Generate cache for listing pages whose sources have changed, including
+/// index, taxonomy and archive pages, feeds and sitemap.
+fn gen_listing_cache(cached: &CachedArticles, taxonomy: &Taxonomy,
+    manifest: &mut Manifest) {
+    info!("Generating cache for listing pages.");
+    let mut gen = ListingCacheGenerator::new(manifest);
+
+    // Index pages. Pagination depends on the number of pages.
+    let templates = fingerprint_templates(&[&CONFIGS.index_template_path,
+        &CONFIGS.digest_template_path, &CONFIGS.pagination_template_path]);
+    let page_count = count_pages(cached);
+    for page in 1..(page_count + 1) {
+        let fp = gen.fingerprint(&templates, &articles_on_page(cached, page),
+            &page_count.to_string());
+        gen.gen(format!("index_{}", page), PAGE_MEDIA_TYPE, fp,
+            || Some(gen_index_page(cached, page)));
+    }
+
+    // Listing pages of terms.
+    let templates = fingerprint_templates(&[&CONFIGS.taxonomy_template_path,
+        &CONFIGS.digest_template_path, &CONFIGS.pagination_template_path]);
+    for kind in &[TaxonomyKind::Tag, TaxonomyKind::Category] {
+        for (term, articles) in taxonomy.terms(*kind) {
+            let page_count = count_pages(articles);
+            for page in 1..(page_count + 1) {
+                let fp = gen.fingerprint(&templates,
+                    &articles_on_page(articles, page),
+                    &format!("{} {}", page_count, term));
+                gen.gen(term_cache_name(*kind, term, page), PAGE_MEDIA_TYPE,
+                    fp,
+                    || gen_term_page(taxonomy, *kind, term, page));
+            }
+        }
+    }
+    // Overview of terms only shows names and counts of terms.
+    let templates = fingerprint_templates(&[&CONFIGS.tags_template_path]);
+    let mut terms = String::new();
+    for kind in &[TaxonomyKind::Tag, TaxonomyKind::Category] {
+        for (term, articles) in taxonomy.terms(*kind) {
+            terms += &format!("{} {} {}\n",
+                kind.url_prefix(), term, articles.len());
+        }
+    }
+    let fp = gen.fingerprint(&templates, &CachedArticles::new(), &terms);
+    gen.gen("tags".to_owned(), PAGE_MEDIA_TYPE, fp,
+        || Some(gen_terms_page(taxonomy)));
+
+    // Archive pages. Every archive page shows all periods.
+    let templates = fingerprint_templates(&[&CONFIGS.archive_template_path]);
+    let counts = count_by_month(cached);
+    let periods_fp = format!("{:?}", &counts);
+    let mut periods = vec![(None, None)];
+    for (year, months) in counts {
+        periods.push((Some(year), None));
+        periods.extend(months.keys().map(|month| (Some(year), Some(*month))));
+    }
+    for (year, month) in periods {
+        let articles = cached.iter()
+            .filter(|&(dt, _)| year.map_or(true, |year| dt.year() == year) &&
+                month.map_or(true, |month| dt.month() == month))
+            .map(|(dt, name)| (*dt, name.clone()))
+            .collect();
+        let fp = gen.fingerprint(&templates, &articles, &periods_fp);
+        gen.gen(archive_cache_name(year, month), PAGE_MEDIA_TYPE, fp,
+            || gen_archive_page(cached, year, month));
+    }
+
+    // Feeds and sitemap depend on settings of site, which might change
+    // between runs if cache is kept.
+    let site = format!("{} {} {} {} {}", &CONFIGS.site_url,
+        &CONFIGS.site_title, &CONFIGS.site_description,
+        CONFIGS.feed_item_count, CONFIGS.feed_full_content);
+    let latest = cached.iter().rev()
+        .take(CONFIGS.feed_item_count as usize)
+        .map(|(dt, name)| (*dt, name.clone()))
+        .collect();
+    for kind in &[FeedKind::Rss, FeedKind::Atom, FeedKind::Json] {
+        let fp = gen.fingerprint("", &latest, &site);
+        gen.gen(kind.cache_name().to_owned(), kind.media_type(), fp,
+            || Some(Ok(gen_feed(cached, *kind))));
+    }
+    let fp = gen.fingerprint("", cached, &format!("{}{}", &site, &terms));
+    gen.gen("sitemap".to_owned(), SITEMAP_MEDIA_TYPE, fp,
+        || Some(Ok(gen_sitemap(cached, taxonomy))));
+
+    gen.remove_stale();
+}
+/// Compressed variants of cached pages depend on settings of compression. If
+/// the settings have changed since last generation, all cache is removed so
+/// that every page is generated again.
+fn check_compression_settings(manifest: &mut Manifest) {
+    let compression = fingerprint(&[], &format!("{} {}",
+        CONFIGS.compress_min_size, CONFIGS.compress_media_types.join(",")));
+    let changed = manifest.get("compression")
+        .map_or(true, |entry| entry.fingerprint != compression);
+    if changed && !manifest.keys().is_empty() {
+        info!("Settings of compression have changed.");
+        remove_cache();
+        *manifest = Manifest::load();
+    }
+    manifest.insert("compression".to_owned(), ManifestEntry {
+        fingerprint: compression,
+        published: None,
+    });
+}
+/// Generate cache for all articles and listing pages. Only pages whose sources
+/// have changed since last generation are generated again. Published articles,
+/// scheduled ones and the taxonomy of published articles are returned.
+pub fn gen_cache() -> (CachedArticles, CachedArticles, Taxonomy) {
+    // Take the time before generation, so that articles published during
+    // generation are published again by `publish_scheduled()`.
+    let now = Utc::now();
+    let mut manifest = Manifest::load();
+    check_compression_settings(&mut manifest);
+    let mut cached = gen_article_cache(&mut manifest);
+    let scheduled = cached.split_off(&now);
+    let taxonomy = Taxonomy::build(&cached);
+    gen_listing_cache(&cached, &taxonomy, &mut manifest);
+    manifest.save();
+    gen_material_cache();
+    (cached, scheduled, taxonomy)
+}
+/// Publish scheduled articles whose time has come. Caches of the articles are
+/// generated, and taxonomy and listing pages are updated if any article is
+/// published.
+///
+/// Returns true if any article is published.
+pub fn publish_scheduled(cached: &mut CachedArticles,
+    scheduled: &mut CachedArticles, taxonomy: &mut Taxonomy) -> bool {
+    let later = scheduled.split_off(&Utc::now());
+    let due = ::std::mem::replace(scheduled, later);
+    if due.is_empty() { return false; }
+    let mut published = false;
+    for (_, name) in due {
+        info!("Publishing scheduled article: {}", &name);
+        match gen_single_cache(name) {
+            // It's rescheduled to a later time in the meantime.
+            Some((dt, name)) if dt > Utc::now() => {
+                info!("...But it's rescheduled to {}.", dt.to_rfc3339());
+                scheduled.insert(dt, name);
+            },
+            Some((dt, name)) => {
+                cached.insert(dt, name);
+                published = true;
+            },
+            // Unlisted articles are cached but not indexed.
+            None => {},
+        }
+    }
+    if !published { return false; }
+    *taxonomy = Taxonomy::build(cached);
+    let mut manifest = Manifest::load();
+    gen_listing_cache(cached, taxonomy, &mut manifest);
+    manifest.save();
+    true
 }
 
 //// Cache loading.
 //
 
-fn load_cached_article(local_path: &Path) -> Option<String> {
-    match local_path.canonicalize() {
-        Ok(name) => {
-            let name = match name.file_name().and_then(OsStr::to_str) {
-                Some(nm) => nm,
-                None => return None,
-            };
-            let mut cache_path =
-                path_buf![&CONFIGS.cache_dir, "post", name];
-            cache_path.set_extension("writiumcache");
-            load_text_resource(&cache_path)
-        },
-        Err(_) => None,
-    }
+fn load_cached_article(local_path: &Path) -> Option<Resource> {
+    // Single-file articles don't have directories to be canonicalized.
+    let name = if local_path.exists() {
+        local_path.canonicalize().ok()
+    } else {
+        locate_article_content(local_path).map(|_| local_path.to_owned())
+    };
+    let name = match name.as_ref()
+        .and_then(|nm| nm.file_name())
+        .and_then(OsStr::to_str) {
+        Some(nm) => nm,
+        None => return None,
+    };
+    load_cached(&article_cache_path(name))
 }
-fn load_cached_index_page(page: u32) -> Option<String> {
-    let cache_path =
-        path_buf![&CONFIGS.cache_dir, format!("index_{}.writiumcache", page)];
-    load_text_resource(&cache_path)
+/// Path to cache file of page other than articles.
+fn page_cache_path(file_name: &str) -> PathBuf {
+    path_buf![&CONFIGS.cache_dir, format!("{}.writiumcache", file_name)]
+}
+/// Load cached page named $file_name along with its compressed variants.
+pub fn load_cached_page(file_name: &str) -> Option<Resource> {
+    load_cached(&page_cache_path(file_name))
+}
+fn load_cached(cache_path: &Path) -> Option<Resource> {
+    load_text_resource(cache_path).map(|content| Resource::Article {
+        content: content,
+        // Variants older than the page are left by an interrupted update.
+        precompressed: load_precompressed(cache_path, cache_path),
+    })
 }
 
 //
@@ -413,20 +979,31 @@ pub fn get_material(local_path: &Path, media_type: &str) -> Option<Resource> {
         Some(data) => Some(Material {
             media_type: media_type.to_owned(),
             data: data,
+            precompressed: load_precompressed_material(local_path),
         }),
         None => Some(InvalidMaterial),
     }
 }
-pub fn get_article(local_path: &Path) -> Option<Resource> {
-    if !local_path.is_dir() { return None; }
+/// Generate article. Drafts and scheduled articles are available only in
+/// preview.
+pub fn get_article(local_path: &Path, preview: bool) -> Option<Resource> {
+    if locate_article_content(local_path).is_none() { return None; }
     let mut vars = get_template_vars(local_path);
+    if ArticleStatus::from_vars(&vars) == ArticleStatus::Draft ||
+        is_scheduled(&vars) {
+        if !preview {
+            info!("Requested article is not published yet.");
+            return Some(Resource::InvalidArticle);
+        }
+        info!("Previewing unpublished article.");
+    }
     gen_article_given_vars(local_path, &mut vars)
 }
 
 /// Get resource file.
-pub fn get_resource(local_path: &Path, can_be_article: bool)
+pub fn get_resource(local_path: &Path, can_be_article: bool, preview: bool)
     -> Option<Resource> {
-    use self::Resource::{AddSlash, Article};
+    use self::Resource::AddSlash;
 
     match deduce_type_by_ext(local_path) {
         // Extension present, return material.
@@ -443,12 +1020,12 @@ pub fn get_resource(local_path: &Path, can_be_article: bool)
             // Look for cached pages first.
             if let Some(cached) = load_cached_article(&local_path) {
                 info!("Found cache. Use cached page instead.");
-                return Some(Article{ content: cached });
+                return Some(cached);
             }
             warn!("Cache not found. Generate page now.");
 
             // Cache not found, generate now.
-            get_article(&local_path)
+            get_article(&local_path, preview)
         } else {
             // Unrecognized resource type.
             None
@@ -456,18 +1033,137 @@ pub fn get_resource(local_path: &Path, can_be_article: bool)
     }
 }
 
+/// Convert generated page to resource.
+fn page_to_resource(page: Result<String, TemplateError>) -> Option<Resource> {
+    match page {
+        Ok(content) => Some(Resource::Article {
+            content: content,
+            precompressed: Precompressed::default(),
+        }),
+        Err(err) => {
+            error!("Unable to generate page: {}", err);
+            Some(Resource::InvalidTemplate { error: err })
+        },
+    }
+}
+
 /// Get index page.
 pub fn get_index_page(cached: &CachedArticles, page: u32) -> Option<Resource> {
     let real_page = if page == 0 { 1 } else { page };
     
-    if let Some(cached) = load_cached_index_page(real_page) {
+    if let Some(cached) = load_cached_page(&format!("index_{}", real_page)) {
         info!("Found cache. Use cached page instead.");
-        return Some(Resource::Article{ content: cached });
+        return Some(cached);
     }
     warn!("Cache not found. Generate page now.");
-    let digests = gen_digests(cached, real_page);
-    match gen_index_page_given_digest(cached, digests, real_page) {
-        Some(content) => Some(Resource::Article{ content: content }),
-        None => None,
+    page_to_resource(gen_index_page(cached, real_page))
+}
+/// Get listing page of a term. None is returned if no listed article has the
+/// term.
+pub fn get_term_page(taxonomy: &Taxonomy, kind: TaxonomyKind, term: &str,
+    page: u32) -> Option<Resource> {
+    let real_page = if page == 0 { 1 } else { page };
+    if let Some(cached) =
+        load_cached_page(&term_cache_name(kind, term, real_page)) {
+        info!("Found cache. Use cached page instead.");
+        return Some(cached);
+    }
+    warn!("Cache not found. Generate page now.");
+    gen_term_page(taxonomy, kind, term, real_page).and_then(page_to_resource)
+}
+/// Get archive page of the given period. None is returned if no article is
+/// published in the period.
+pub fn get_archive_page(cached: &CachedArticles, year: Option<i32>,
+    month: Option<u32>) -> Option<Resource> {
+    if let Some(cached) = load_cached_page(&archive_cache_name(year, month)) {
+        info!("Found cache. Use cached page instead.");
+        return Some(cached);
+    }
+    warn!("Cache not found. Generate page now.");
+    gen_archive_page(cached, year, month).and_then(page_to_resource)
+}
+/// Get overview of all terms.
+pub fn get_terms_page(taxonomy: &Taxonomy) -> Option<Resource> {
+    if let Some(cached) = load_cached_page("tags") {
+        info!("Found cache. Use cached page instead.");
+        return Some(cached);
+    }
+    warn!("Cache not found. Generate page now.");
+    page_to_resource(gen_terms_page(taxonomy))
+}
+
+#[cfg(test)]
+mod tests {
+    use writium::chrono::Duration;
+
+    use super::*;
+
+    /// Write content of article named $name into post directory, and return
+    /// the local path of the article.
+    fn write_article(name: &str, content: &[u8]) -> PathBuf {
+        let local_path = path_buf![&CONFIGS.post_dir, name];
+        fs::create_dir_all(&local_path).unwrap();
+        File::create(path_buf![&local_path, "content.md"]).unwrap()
+            .write_all(content).unwrap();
+        local_path
+    }
+
+    #[test]
+    fn rescheduled_article() {
+        let name = "rescheduled";
+        let local_path = write_article(name, b"Title\ncontent");
+        let later = Utc::now() + Duration::days(1);
+        File::create(path_buf![&local_path, "metadata.json"]).unwrap()
+            .write_all(format!("{{\"published\": \"{}\"}}",
+                later.to_rfc3339()).as_bytes()).unwrap();
+        // It was scheduled to be published an hour ago.
+        let mut scheduled = CachedArticles::new();
+        scheduled.insert(Utc::now() - Duration::hours(1), name.to_owned());
+        let mut cached = CachedArticles::new();
+        let mut taxonomy = Taxonomy::build(&cached);
+        assert!(!publish_scheduled(&mut cached, &mut scheduled,
+            &mut taxonomy));
+        assert!(cached.is_empty());
+        assert_eq!(scheduled.len(), 1);
+        let (dt, scheduled_name) = scheduled.iter().next().unwrap();
+        assert_eq!(dt.timestamp(), later.timestamp());
+        assert_eq!(scheduled_name, name);
+    }
+    #[test]
+    fn status() {
+        let status_of = |status: &str| {
+            let mut vars = TemplateVariables::new();
+            vars.insert("status".to_owned(), status);
+            ArticleStatus::from_vars(&vars)
+        };
+        assert!(status_of("draft") == ArticleStatus::Draft);
+        assert!(status_of(" Draft\n") == ArticleStatus::Draft);
+        assert!(status_of("UNLISTED") == ArticleStatus::Unlisted);
+        assert!(status_of("published") == ArticleStatus::Published);
+        assert!(status_of("") == ArticleStatus::Published);
+        assert!(ArticleStatus::from_vars(&TemplateVariables::new()) ==
+            ArticleStatus::Published);
+    }
+    #[test]
+    fn line_endings() {
+        let title_content = |name, content: &str| {
+            let local_path = write_article(name, content.as_bytes());
+            get_article_title_content_markdown(&local_path)
+        };
+        let expected = Some(("Title".to_owned(), "a\n\nb".to_owned()));
+        assert_eq!(title_content("lf", "# Title\na\n\nb\n"), expected);
+        assert_eq!(title_content("crlf", "# Title\r\na\r\n\r\nb\r\n"),
+            expected);
+        assert_eq!(title_content("mixed", "# Title\r\na\n\rb"), expected);
+        assert_eq!(title_content("bom", "\u{feff}# Title\na\n\nb"), expected);
+        let title_only = Some(("Title".to_owned(), String::new()));
+        assert_eq!(title_content("title-only", "Title"), title_only);
+        assert_eq!(title_content("title-only-lf", "Title\n"), title_only);
+        assert_eq!(title_content("title-only-crlf", "\u{feff}\r\nTitle\r\n"),
+            title_only);
+        assert_eq!(title_content("empty", ""), None);
+        assert_eq!(title_content("blank", "\u{feff}\r\n\n"), None);
+        assert_eq!(load_article_text(&write_article("text", b"a\r\nb\rc\n")),
+            Some("a\nb\nc\n".to_owned()));
     }
 }
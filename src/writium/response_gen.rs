@@ -1,9 +1,15 @@
+use std::io::{Cursor, Read};
+use std::sync::Arc;
+
 use writium::iron::prelude::*;
-use writium::iron::headers::{ContentType};
+use writium::iron::headers::{ContentLength, ContentType};
 use writium::iron::status;
 
+use writium::compress::{compress, is_compressible, Encoding, Precompressed};
+use writium::page_cache::CachedPage;
 use writium::resource;
 use writium::settings::CONFIGS;
+use writium::template::{escape_html, TemplateError};
 
 /// Map error code to error literal.
 fn map_error_code(code: status::Status) -> String {
@@ -40,19 +46,77 @@ fn map_error_code(code: status::Status) -> String {
     }.to_owned()
 }
 
+/// Shared data written as response body without being copied.
+struct SharedBody(Arc<Vec<u8>>);
+impl AsRef<[u8]> for SharedBody {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+/// Response shared data with status OK.
+fn gen_shared(data: Arc<Vec<u8>>) -> Response {
+    let mut res = Response::with(status::Ok);
+    res.headers.set(ContentLength(data.len() as u64));
+    let body: Box<dyn Read + Send> = Box::new(Cursor::new(SharedBody(data)));
+    res.body = Some(Box::new(body));
+    res
+}
+
+/// Response data in the most preferred encoding acceptable to client.
+/// Variants compressed ahead of time are used if there is any. Otherwise,
+/// compressible data are compressed now.
+fn gen_encoded(data: Arc<Vec<u8>>, media_type: &str,
+    precompressed: &Precompressed, accepted: &[Encoding]) -> Response {
+    let compressible = is_compressible(media_type, data.len());
+    // Responses differ by encoding as long as there can be variants.
+    let varies = compressible || !precompressed.is_empty();
+    let encoded = accepted.iter()
+        .filter_map(|encoding| precompressed.get(*encoding)
+            .map(|compressed| (*encoding, compressed)))
+        .next()
+        .or_else(|| match accepted.first() {
+            Some(encoding) if compressible => compress(&data, *encoding, false)
+                .map(|compressed| (*encoding, Arc::new(compressed))),
+            _ => None,
+        });
+    let mut res = match encoded {
+        Some((encoding, compressed)) => {
+            let mut res = gen_shared(compressed);
+            res.headers.set_raw("Content-Encoding",
+                vec![encoding.name().as_bytes().to_owned()]);
+            res
+        },
+        None => gen_shared(data),
+    };
+    if varies {
+        res.headers.set_raw("Vary", vec![b"Accept-Encoding".to_vec()]);
+    }
+    res
+}
 /// Response normal web page with given HTML data.
-pub fn gen_page(html: String) -> Response {
-    let mut res = Response::with((status::Ok, html));
+pub fn gen_page(html: Arc<Vec<u8>>, precompressed: &Precompressed,
+    accepted: &[Encoding]) -> Response {
+    let mut res = gen_encoded(html, "text/html", precompressed, accepted);
     res.headers.set(ContentType::html());
     res
 }
 /// Response materials of special types.
-pub fn gen_spec(data: Vec<u8>, content_type: String) -> Response {
-    let mut res = Response::with((status::Ok, data));
+pub fn gen_spec(data: Arc<Vec<u8>>, content_type: &str,
+    precompressed: &Precompressed, accepted: &[Encoding]) -> Response {
+    let mut res = gen_encoded(data, content_type, precompressed, accepted);
     
-    res.headers.set_raw("Content-Type", vec![content_type.into_bytes()]);
+    res.headers.set_raw("Content-Type",
+        vec![content_type.as_bytes().to_owned()]);
     res
 }
+/// Response page kept in memory.
+pub fn gen_cached(page: &CachedPage, accepted: &[Encoding]) -> Response {
+    match page.media_type {
+        Some(ref media_type) => gen_spec(page.data.clone(), media_type,
+            &page.precompressed, accepted),
+        None => gen_page(page.data.clone(), &page.precompressed, accepted),
+    }
+}
 /// Response error code simply.
 pub fn gen_error(code: status::Status) -> Response {
     let err_code_literal = map_error_code(code);
@@ -74,6 +138,21 @@ pub fn gen_error_page(code: status::Status) -> Response {
         None => gen_error(code),
     }
 }
+/// Response template error. In development mode, details of the error are
+/// shown; otherwise, it's responded as if the page doesn't exist.
+pub fn gen_template_error_page(err: &TemplateError) -> Response {
+    if !CONFIGS.development_mode {
+        return gen_error_page(status::NotFound);
+    }
+    info!("Generating template error page.");
+    let html = format!("<!DOCTYPE html>\n<html>\n<head>\n\
+        <meta charset=\"utf-8\">\n<title>Template Error</title>\n</head>\n\
+        <body>\n<h1>Template Error</h1>\n<pre>{}</pre>\n</body>\n</html>\n",
+        escape_html(&err.to_string()));
+    let mut res = Response::with((status::InternalServerError, html));
+    res.headers.set(ContentType::html());
+    res
+}
 /// Response redirection.
 pub fn gen_redirection(location: &str) -> Response {
     info!("Generating Rediretion to: {}", location);
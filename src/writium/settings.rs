@@ -29,7 +29,25 @@ pub struct WritusConfigs {
 
     /// The directory where cache is output.
     pub cache_dir: String,
+    /// Keep cache on termination, so that only pages changed in the meantime
+    /// are generated at next start. [default: false]
+    pub keep_cache: bool,
+    /// Maximum size in bytes of rendered pages kept in memory. The least
+    /// recently used pages are evicted first. 0 disables it. [default: 0]
+    pub memory_cache_size: usize,
+    /// Minimum size in bytes of pages and static files to be compressed.
+    /// [default: 1024]
+    pub compress_min_size: usize,
+    /// Media types of pages and static files to be compressed, separated by
+    /// commas. Compression is disabled if it's empty. [default: text/html,
+    /// text/css, text/plain, application/javascript, application/json,
+    /// application/xml, application/rss+xml, application/atom+xml,
+    /// application/feed+json, image/svg+xml]
+    pub compress_media_types: Vec<String>,
 
+    /// Archive template file path in $TEMPLATE_DIR. MUST NOT have slash as
+    /// prefix. [default: archive.html]
+    pub archive_template_path: String,
     /// Digest template file path in $TEMPLATE_DIR. MUST NOT have slash as
     /// prefix. [default: digest.html]
     pub digest_template_path: String,
@@ -42,9 +60,45 @@ pub struct WritusConfigs {
     /// Post template file path in $TEMPLATE_DIR. MUST NOT have slash as prefix.
     /// [default: post.html]
     pub post_template_path: String,
+    /// Template file path of overview of tags and categories in $TEMPLATE_DIR.
+    /// MUST NOT have slash as prefix. [default: tags.html]
+    pub tags_template_path: String,
+    /// Template file path of listing pages of tags and categories in
+    /// $TEMPLATE_DIR. MUST NOT have slash as prefix. [default: taxonomy.html]
+    pub taxonomy_template_path: String,
 
     /// Number of digests shown per page on index page. [default: 5]
     pub digests_per_page: u32,
+
+    /// Base URL of site without trailing slash, used to make absolute URLs in
+    /// feeds. [default: http://$HOST_ADDR, or https://$HOST_ADDR_SECURE if SSL
+    /// is enabled]
+    pub site_url: String,
+    /// Title of site shown in feeds. [default: Writium]
+    pub site_title: String,
+    /// Description of site shown in feeds. [default: (empty)]
+    pub site_description: String,
+    /// Number of latest articles included in feeds. [default: 10]
+    pub feed_item_count: u32,
+    /// Include full content of articles in feeds rather than digests.
+    /// [default: false]
+    pub feed_full_content: bool,
+
+    /// Show details of template errors in error pages. It SHOULD NOT be
+    /// enabled on production servers. [default: false]
+    pub development_mode: bool,
+    /// Watch post, template and error directories, and update cache
+    /// automatically when they change. [default: false]
+    pub watch: bool,
+    /// Milliseconds to wait for changes to settle down before cache is
+    /// updated in watch mode. [default: 500]
+    pub watch_delay: u64,
+    /// Serve drafts to everyone. It SHOULD NOT be enabled on production
+    /// servers. [default: false]
+    pub preview_mode: bool,
+    /// Secret token to preview drafts, given as `?preview=<token>`. Previewing
+    /// by token is disabled if it's empty. [default: (empty)]
+    pub preview_token: String,
     
     /// Path to SSL identity.
     /// How to generate:
@@ -75,14 +129,33 @@ impl WritusConfigs {
             root_dir: String::new(),
             
             cache_dir: String::new(),
+            keep_cache: false,
+            memory_cache_size: 0,
+            compress_min_size: 0,
+            compress_media_types: Vec::new(),
             
+            archive_template_path: String::new(),
             digest_template_path: String::new(),
             index_template_path: String::new(),
             pagination_template_path: String::new(),
             post_template_path: String::new(),
+            tags_template_path: String::new(),
+            taxonomy_template_path: String::new(),
 
             digests_per_page: 0,
 
+            site_url: String::new(),
+            site_title: String::new(),
+            site_description: String::new(),
+            feed_item_count: 0,
+            feed_full_content: false,
+
+            development_mode: false,
+            watch: false,
+            watch_delay: 0,
+            preview_mode: false,
+            preview_token: String::new(),
+
             ssl_identity_path: String::new(),
             ssl_password: String::new(),
         }
@@ -138,7 +211,26 @@ impl WritusConfigs {
             configs.root_dir = must_have(&mut obj, "rootDir");
 
             configs.cache_dir = must_have(&mut obj, "cacheDir");
+            configs.keep_cache =
+                have_or(&mut obj, "keepCache", "false") == "true";
+            configs.memory_cache_size =
+                have_or(&mut obj, "memoryCacheSize", "0").parse::<usize>()
+                    .unwrap_or(0);
+            configs.compress_min_size =
+                have_or(&mut obj, "compressMinSize", "1024").parse::<usize>()
+                    .unwrap_or(1024);
+            configs.compress_media_types =
+                have_or(&mut obj, "compressMediaTypes", "text/html,text/css,\
+                    text/plain,application/javascript,application/json,\
+                    application/xml,application/rss+xml,application/atom+xml,\
+                    application/feed+json,image/svg+xml")
+                    .split(',')
+                    .map(|mt| mt.trim().to_lowercase())
+                    .filter(|mt| !mt.is_empty())
+                    .collect();
 
+            configs.archive_template_path =
+                have_or(&mut obj, "archiveTemplatePath", "archive.html");
             configs.digest_template_path =
                 have_or(&mut obj, "digestTemplatePath", "digest.html");
             configs.index_template_path =
@@ -147,6 +239,10 @@ impl WritusConfigs {
                 have_or(&mut obj, "paginationTemplatePath", "pagination.html");
             configs.post_template_path =
                 have_or(&mut obj, "postTemplatePath", "post.html");
+            configs.tags_template_path =
+                have_or(&mut obj, "tagsTemplatePath", "tags.html");
+            configs.taxonomy_template_path =
+                have_or(&mut obj, "taxonomyTemplatePath", "taxonomy.html");
 
             configs.digests_per_page = match obj.get("digestsPerPage")
                 .unwrap_or(&"5".to_owned())
@@ -155,10 +251,40 @@ impl WritusConfigs {
                 Err(_) => 5,
             };
 
+            configs.site_url = have_or(&mut obj, "siteUrl", "")
+                .trim_end_matches('/')
+                .to_owned();
+            configs.site_title = have_or(&mut obj, "siteTitle", "Writium");
+            configs.site_description = have_or(&mut obj, "siteDescription", "");
+            configs.feed_item_count =
+                have_or(&mut obj, "feedItemCount", "10").parse::<u32>()
+                    .unwrap_or(10);
+            configs.feed_full_content =
+                have_or(&mut obj, "feedFullContent", "false") == "true";
+
+            configs.development_mode =
+                have_or(&mut obj, "developmentMode", "false") == "true";
+            configs.watch = have_or(&mut obj, "watch", "false") == "true";
+            configs.watch_delay =
+                have_or(&mut obj, "watchDelay", "500").parse::<u64>()
+                    .unwrap_or(500);
+            configs.preview_mode =
+                have_or(&mut obj, "previewMode", "false") == "true";
+            configs.preview_token =
+                have_or(&mut obj, "previewToken", "");
+
             configs.ssl_identity_path =
                 have_or(&mut obj, "sslIdentityPath", "");
             configs.ssl_password =
                 have_or(&mut obj, "sslPassword", "");
+
+            if configs.site_url.is_empty() {
+                configs.site_url = if configs.ssl_identity_path.is_empty() {
+                    format!("http://{}", configs.host_addr)
+                } else {
+                    format!("https://{}", configs.host_addr_secure)
+                };
+            }
         }
 
         let mut rv = WritusConfigs::new();
@@ -189,8 +315,43 @@ impl WritusConfigs {
         }
         rv
     }
+    /// Settings of unit tests. Directories are in the temporary directory and
+    /// created on demand by tests. Other items are filled by default.
+    #[cfg(test)]
+    fn for_test() -> WritusConfigs {
+        let root = ::std::env::temp_dir()
+            .join(format!("writium-test-{}", ::std::process::id()));
+        let dir = |name: &str| root.join(name).to_string_lossy().into_owned();
+        let mut rv = WritusConfigs::new();
+        rv.host_addr = "127.0.0.1:8080".to_owned();
+        rv.post_dir = dir("post");
+        rv.error_dir = dir("error");
+        rv.template_dir = dir("template");
+        rv.static_dir = dir("static");
+        rv.root_dir = dir("root");
+        rv.cache_dir = dir("cache");
+        rv.compress_min_size = 1024;
+        rv.archive_template_path = "archive.html".to_owned();
+        rv.digest_template_path = "digest.html".to_owned();
+        rv.index_template_path = "index.html".to_owned();
+        rv.pagination_template_path = "pagination.html".to_owned();
+        rv.post_template_path = "post.html".to_owned();
+        rv.tags_template_path = "tags.html".to_owned();
+        rv.taxonomy_template_path = "taxonomy.html".to_owned();
+        rv.digests_per_page = 5;
+        rv.site_url = format!("http://{}", rv.host_addr);
+        rv.site_title = "Writium".to_owned();
+        rv.feed_item_count = 10;
+        rv.watch_delay = 500;
+        rv
+    }
 }
 
+#[cfg(not(test))]
 lazy_static! {
     pub static ref CONFIGS: WritusConfigs = WritusConfigs::from_args();
 }
+#[cfg(test)]
+lazy_static! {
+    pub static ref CONFIGS: WritusConfigs = WritusConfigs::for_test();
+}
@@ -0,0 +1,85 @@
+use writium::compress::Precompressed;
+use writium::feed::escape_xml;
+use writium::resource::{get_template_vars, load_cached_page, load_resource,
+    parse_date_time, CachedArticles, Resource};
+use writium::settings::CONFIGS;
+use writium::taxonomy::{term_path, Taxonomy, TaxonomyKind};
+use writium::template::encode_url_component;
+
+pub const SITEMAP_MEDIA_TYPE: &'static str = "application/xml";
+
+/// Append an URL entry to sitemap. $path is relative to site URL.
+fn push_url(rv: &mut String, path: &str, lastmod: Option<String>) {
+    *rv += "<url>\n";
+    *rv += &format!("<loc>{}{}</loc>\n",
+        escape_xml(&CONFIGS.site_url), escape_xml(path));
+    if let Some(lastmod) = lastmod {
+        *rv += &format!("<lastmod>{}</lastmod>\n", lastmod);
+    }
+    *rv += "</url>\n";
+}
+
+pub fn gen_sitemap(cached: &CachedArticles, taxonomy: &Taxonomy) -> String {
+    let mut rv = String::new();
+    rv += "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n";
+    rv += "<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n";
+    // Index pages.
+    push_url(&mut rv, "/", None);
+    let per_page = (CONFIGS.digests_per_page as usize).max(1);
+    let page_count = (cached.len() + per_page - 1) / per_page;
+    for page in 2..(page_count + 1) {
+        push_url(&mut rv, &format!("/?page={}", page), None);
+    }
+    // Listed articles.
+    for name in cached.values() {
+        let vars = get_template_vars(&path_buf![&CONFIGS.post_dir, name]);
+        push_url(&mut rv, &format!("/post/{}/", encode_url_component(name)),
+            parse_date_time(&vars, "modified").map(|dt| dt.to_rfc3339()));
+    }
+    // Taxonomy pages.
+    push_url(&mut rv, "/tags/", None);
+    for kind in &[TaxonomyKind::Tag, TaxonomyKind::Category] {
+        for term in taxonomy.terms(*kind).keys() {
+            push_url(&mut rv, &term_path(*kind, term), None);
+        }
+    }
+    rv += "</urlset>\n";
+    rv
+}
+
+/// Get sitemap of listed articles and listing pages.
+pub fn get_sitemap(cached: &CachedArticles, taxonomy: &Taxonomy)
+    -> Option<Resource> {
+    let (content, precompressed) = match load_cached_page("sitemap") {
+        Some(Resource::Article { content, precompressed }) => {
+            info!("Found cache. Use cached sitemap instead.");
+            (content, precompressed)
+        },
+        _ => {
+            warn!("Cache not found. Generate sitemap now.");
+            (gen_sitemap(cached, taxonomy), Precompressed::default())
+        },
+    };
+    Some(Resource::Material {
+        media_type: SITEMAP_MEDIA_TYPE.to_owned(),
+        data: content.into_bytes(),
+        precompressed: precompressed,
+    })
+}
+
+/// Get `robots.txt` in root directory. If there isn't one, a default one
+/// allowing everything and pointing to sitemap is given.
+pub fn get_robots() -> Option<Resource> {
+    let path = path_buf![&CONFIGS.root_dir, "robots.txt"];
+    let data = match load_resource(&path) {
+        Some(data) => data,
+        None => format!(
+            "User-agent: *\nAllow: /\n\nSitemap: {}/sitemap.xml\n",
+            &CONFIGS.site_url).into_bytes(),
+    };
+    Some(Resource::Material {
+        media_type: "text/plain".to_owned(),
+        data: data,
+        precompressed: Precompressed::default(),
+    })
+}
@@ -0,0 +1,102 @@
+use std::collections::BTreeMap;
+
+use writium::resource::{get_template_vars, CachedArticles};
+use writium::settings::CONFIGS;
+use writium::template::{encode_url_component, Value};
+
+/// Kind of taxonomy. Terms of articles are given in variables `tags` and
+/// `categories`.
+#[derive(Clone, Copy, PartialEq)]
+pub enum TaxonomyKind {
+    Tag,
+    Category,
+}
+impl TaxonomyKind {
+    /// Name of the variable listing terms of article.
+    pub fn var_name(&self) -> &'static str {
+        match *self {
+            TaxonomyKind::Tag => "tags",
+            TaxonomyKind::Category => "categories",
+        }
+    }
+    /// First segment of path to listing pages of terms.
+    pub fn url_prefix(&self) -> &'static str {
+        match *self {
+            TaxonomyKind::Tag => "tag",
+            TaxonomyKind::Category => "category",
+        }
+    }
+}
+
+/// Listed articles grouped by terms.
+pub type Terms = BTreeMap<String, CachedArticles>;
+
+/// Tags and categories of listed articles.
+pub struct Taxonomy {
+    tags: Terms,
+    categories: Terms,
+}
+impl Taxonomy {
+    pub fn new() -> Taxonomy {
+        Taxonomy {
+            tags: Terms::new(),
+            categories: Terms::new(),
+        }
+    }
+    /// Group listed articles by their tags and categories.
+    pub fn build(cached: &CachedArticles) -> Taxonomy {
+        info!("Building taxonomy.");
+        let mut taxonomy = Taxonomy::new();
+        for (dt, name) in cached {
+            let vars =
+                get_template_vars(&path_buf![&CONFIGS.post_dir, name, ""]);
+            for kind in &[TaxonomyKind::Tag, TaxonomyKind::Category] {
+                let terms = taxonomy.terms_mut(*kind);
+                for term in get_terms(vars.get(kind.var_name())) {
+                    terms.entry(term)
+                        .or_insert_with(CachedArticles::new)
+                        .insert(*dt, name.clone());
+                }
+            }
+        }
+        taxonomy
+    }
+
+    pub fn terms(&self, kind: TaxonomyKind) -> &Terms {
+        match kind {
+            TaxonomyKind::Tag => &self.tags,
+            TaxonomyKind::Category => &self.categories,
+        }
+    }
+    fn terms_mut(&mut self, kind: TaxonomyKind) -> &mut Terms {
+        match kind {
+            TaxonomyKind::Tag => &mut self.tags,
+            TaxonomyKind::Category => &mut self.categories,
+        }
+    }
+    /// Get articles of the given term.
+    pub fn get(&self, kind: TaxonomyKind, term: &str)
+        -> Option<&CachedArticles> {
+        self.terms(kind).get(term)
+    }
+}
+
+/// Get terms from variable. A single term can be given as text.
+pub fn get_terms(val: Option<&Value>) -> Vec<String> {
+    let texts: Vec<&str> = match val {
+        Some(&Value::List(ref items)) =>
+            items.iter().filter_map(Value::as_text).collect(),
+        Some(val) => val.as_text().into_iter().collect(),
+        None => Vec::new(),
+    };
+    texts.into_iter()
+        .map(str::trim)
+        .filter(|term| !term.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Path to the listing page of term.
+pub fn term_path(kind: TaxonomyKind, term: &str) -> String {
+    format!("/{}/{}/", kind.url_prefix(), encode_url_component(term))
+}
@@ -1,18 +1,868 @@
-use std::collections::BTreeMap;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use std::fs::metadata;
 use std::fs::Metadata;
 use std::io;
-use std::path::Path;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
 use std::time::SystemTime;
 
 use writium::chrono;
-use writium::chrono::Utc;
+use writium::chrono::{DateTime, Utc};
+use writium::chrono::format::{Item, StrftimeItems};
 
+use writium::front_matter::split_front_matter;
+use writium::json::JsonValue;
 use writium::resource;
 use writium::settings::CONFIGS;
 
+/// Location in template file.
+#[derive(Clone, Debug)]
+pub struct Location {
+    /// Template path in $TEMPLATE_DIR.
+    pub path: String,
+    /// 1-based line number.
+    pub line: usize,
+    /// 1-based column number, counted in characters.
+    pub column: usize,
+}
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.path, self.line, self.column)
+    }
+}
+
+/// Kind of template error.
+#[derive(Debug)]
+pub enum TemplateErrorKind {
+    /// A processing instruction is opened with `<?` but never closed with `?>`.
+    UnterminatedTag,
+    /// A block instruction, like `else` or `endif`, doesn't match any opened
+    /// block.
+    UnbalancedBlock(String),
+    /// A block is not closed till the end of template. The missing closing
+    /// directive is carried.
+    UnclosedBlock(String),
+    /// Malformed instruction, like a `for` without `in`.
+    InvalidSyntax(String),
+    /// Directive of processing instruction is not recognized.
+    UnknownDirective(String),
+    /// A template, like a fragment or a layout to be extended, doesn't exist.
+    MissingTemplate(String),
+    /// Fragments or layouts include themselves, directly or indirectly. The
+    /// include chain is carried.
+    IncludeCycle(Vec<String>),
+    /// Fragments or layouts are nested too deeply. The include chain is
+    /// carried.
+    IncludeTooDeep(Vec<String>),
+}
+impl fmt::Display for TemplateErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::TemplateErrorKind::*;
+        match *self {
+            UnterminatedTag =>
+                write!(f, "processing instruction is not terminated with `?>`"),
+            UnbalancedBlock(ref directive) =>
+                write!(f, "`{}` doesn't match any opened block", directive),
+            UnclosedBlock(ref directive) =>
+                write!(f, "block is not closed with `{}`", directive),
+            InvalidSyntax(ref instruction) =>
+                write!(f, "invalid syntax in `{}`", instruction),
+            UnknownDirective(ref directive) =>
+                write!(f, "unknown directive `{}`", directive),
+            MissingTemplate(ref path) =>
+                write!(f, "template `{}` cannot be read", path),
+            IncludeCycle(ref chain) =>
+                write!(f, "cyclic inclusion: {}", chain.join(" -> ")),
+            IncludeTooDeep(ref chain) =>
+                write!(f, "inclusion nested more than {} levels: {}",
+                    MAX_INCLUDE_DEPTH, chain.join(" -> ")),
+        }
+    }
+}
+
+/// Error occurred when a template cannot be filled.
+#[derive(Debug)]
+pub struct TemplateError {
+    pub kind: TemplateErrorKind,
+    /// Where the error occurred. It's None when the error is not caused by
+    /// any specific template file, e.g., the top-level template is missing.
+    pub location: Option<Location>,
+}
+impl TemplateError {
+    fn new(kind: TemplateErrorKind) -> TemplateError {
+        TemplateError {
+            kind: kind,
+            location: None,
+        }
+    }
+    fn at(kind: TemplateErrorKind, location: &Location) -> TemplateError {
+        TemplateError {
+            kind: kind,
+            location: Some(location.clone()),
+        }
+    }
+    /// Assign location to error if it has not been located yet.
+    fn or_at(mut self, location: &Location) -> TemplateError {
+        if self.location.is_none() {
+            self.location = Some(location.clone());
+        }
+        self
+    }
+}
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.location {
+            Some(ref location) => write!(f, "{}: {}", location, self.kind),
+            None => write!(f, "{}", self.kind),
+        }
+    }
+}
+
+/// Value of a template variable.
+#[derive(Clone, Debug)]
+pub enum Value {
+    Text(String),
+    /// Pre-rendered HTML. It's never escaped when inserted.
+    Html(String),
+    Bool(bool),
+    Number(f64),
+    List(Vec<Value>),
+    Object(BTreeMap<String, Value>),
+}
+impl Value {
+    /// Get text if the value is a text or HTML.
+    pub fn as_text(&self) -> Option<&str> {
+        match *self {
+            Value::Text(ref st) | Value::Html(ref st) => Some(st),
+            _ => None,
+        }
+    }
+    /// Get field of object by name, or item of list by 0-based index. None
+    /// is returned if the value is neither an object nor a list, or the field
+    /// or item is absent.
+    pub fn field(&self, name: &str) -> Option<&Value> {
+        match *self {
+            Value::Object(ref obj) => obj.get(name),
+            Value::List(ref list) => name.parse::<usize>().ok()
+                .and_then(|idx| list.get(idx)),
+            _ => None,
+        }
+    }
+    /// A value is considered set only when it's not empty, `false` or zero.
+    pub fn is_set(&self) -> bool {
+        match *self {
+            Value::Text(ref st) | Value::Html(ref st) => !st.is_empty(),
+            Value::Bool(b) => b,
+            Value::Number(num) => num != 0.0,
+            Value::List(ref list) => !list.is_empty(),
+            Value::Object(ref obj) => !obj.is_empty(),
+        }
+    }
+}
+impl fmt::Display for Value {
+    /// Texts and HTML are written as-is; booleans are written as `true` or
+    /// `false`; integral numbers are written without fractional part; lists
+    /// are written item by item, separated by commas; objects are not written
+    /// at all.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Text(ref st) | Value::Html(ref st) => f.write_str(st),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Number(num) => {
+                if num.fract() == 0.0 && num.abs() < 1e15 {
+                    write!(f, "{}", num as i64)
+                } else {
+                    write!(f, "{}", num)
+                }
+            },
+            Value::List(ref list) => {
+                for (i, item) in list.iter().enumerate() {
+                    if i > 0 { f.write_str(", ")?; }
+                    write!(f, "{}", item)?;
+                }
+                Ok(())
+            },
+            Value::Object(_) => Ok(()),
+        }
+    }
+}
+impl From<String> for Value {
+    fn from(st: String) -> Value { Value::Text(st) }
+}
+impl<'a> From<&'a str> for Value {
+    fn from(st: &'a str) -> Value { Value::Text(st.to_owned()) }
+}
+impl From<bool> for Value {
+    fn from(b: bool) -> Value { Value::Bool(b) }
+}
+impl From<Vec<Value>> for Value {
+    fn from(list: Vec<Value>) -> Value { Value::List(list) }
+}
+impl<'a> From<&'a JsonValue> for Value {
+    /// Convert JSON value. Nulls are converted to empty texts.
+    fn from(json: &'a JsonValue) -> Value {
+        match *json {
+            JsonValue::Boolean(b) => Value::Bool(b),
+            JsonValue::Number(_) =>
+                Value::Number(json.as_f64().unwrap_or_default()),
+            JsonValue::Array(ref arr) =>
+                Value::List(arr.iter().map(Value::from).collect()),
+            JsonValue::Object(ref obj) => Value::Object(obj.iter()
+                .map(|(key, val)| (key.to_owned(), Value::from(val)))
+                .collect()),
+            _ => Value::from(json.as_str().unwrap_or_default()),
+        }
+    }
+}
+impl From<TemplateVariables> for Value {
+    fn from(vars: TemplateVariables) -> Value { Value::Object(vars.vars) }
+}
+
+/// Escape text so that it can be safely inserted into HTML text or quoted
+/// attribute values.
+pub fn escape_html(text: &str) -> String {
+    let mut rv = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => rv.push_str("&amp;"),
+            '<' => rv.push_str("&lt;"),
+            '>' => rv.push_str("&gt;"),
+            '"' => rv.push_str("&quot;"),
+            '\'' => rv.push_str("&#39;"),
+            _ => rv.push(ch),
+        }
+    }
+    rv
+}
+/// Escape text so that it can be safely used as a URL in quoted attribute
+/// values, like `href="..."`. Characters not allowed in URLs are
+/// percent-encoded, and URLs of scripting schemes are replaced by `#`.
+pub fn escape_url(text: &str) -> String {
+    let text = text.trim();
+    // Scheme ends at the first colon, only if no path, query or fragment
+    // delimiter appears before it.
+    if let Some(pos) = text.find(|ch| ch == ':' || ch == '/' || ch == '?' ||
+        ch == '#') {
+        if text[pos..].starts_with(':') {
+            let scheme = text[..pos].to_lowercase();
+            if scheme == "javascript" || scheme == "vbscript" ||
+                scheme == "data" {
+                warn!("URL of unsafe scheme is blocked: {}", scheme);
+                return "#".to_owned();
+            }
+        }
+    }
+    let mut rv = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        if byte.is_ascii_alphanumeric() ||
+            b"-._~:/?#[]@!$()*+,;=%".contains(&byte) {
+            rv.push(byte as char);
+        } else if byte == b'&' {
+            // Ampersands are valid in URLs but must be escaped in HTML.
+            rv.push_str("&amp;");
+        } else {
+            rv.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    rv
+}
+
+/// Percent-encode everything except unreserved characters, so that the text
+/// can be used as a URL component.
+pub fn encode_url_component(text: &str) -> String {
+    let mut rv = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            rv.push(byte as char);
+        } else {
+            rv.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    rv
+}
+/// Strip tags from HTML and decode basic character references, so that the
+/// plain text can be processed by filters.
+fn html_to_text(html: &str) -> String {
+    let mut rv = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for ch in html.chars() {
+        match ch {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            _ if !in_tag => rv.push(ch),
+            _ => {},
+        }
+    }
+    rv.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Filter applied to variable before insertion, like `| upper`.
+enum Filter {
+    /// `date "format"`. Format RFC 3339 time with `strftime`-like format.
+    Date(String),
+    /// `truncate n`. Keep at most `n` characters.
+    Truncate(usize),
+    /// `upper`.
+    Upper,
+    /// `lower`.
+    Lower,
+    /// `urlencode`. Percent-encode as URL component.
+    UrlEncode,
+    /// `default "text"`. Used when the variable is absent or empty.
+    Default(String),
+}
+impl Filter {
+    /// Parse filter from its name and arguments. None is returned if the
+    /// filter is unknown or its arguments are invalid.
+    fn parse(name: &str, args: &[String]) -> Option<Filter> {
+        match (name, args.len()) {
+            ("date", 1) => {
+                // Reject invalid format ahead of time, or formatting will
+                // fail.
+                if StrftimeItems::new(&args[0]).any(|item| item == Item::Error) {
+                    None
+                } else {
+                    Some(Filter::Date(args[0].to_owned()))
+                }
+            },
+            ("truncate", 1) => args[0].parse().ok().map(Filter::Truncate),
+            ("upper", 0) => Some(Filter::Upper),
+            ("lower", 0) => Some(Filter::Lower),
+            ("urlencode", 0) => Some(Filter::UrlEncode),
+            ("default", 1) => Some(Filter::Default(args[0].to_owned())),
+            _ => None,
+        }
+    }
+    fn apply<'a>(&self, val: Option<Cow<'a, Value>>) -> Option<Cow<'a, Value>> {
+        /// Get plain text of value.
+        fn text_of(val: &Value) -> String {
+            match *val {
+                Value::Html(ref html) => html_to_text(html),
+                _ => val.to_string(),
+            }
+        }
+
+        if let Filter::Default(ref def) = *self {
+            return match val {
+                Some(ref v) if v.is_set() => val,
+                _ => Some(Cow::Owned(Value::from(def.as_str()))),
+            };
+        }
+        let val = val?;
+        let filtered = match *self {
+            Filter::Date(ref format) => {
+                match DateTime::parse_from_rfc3339(&text_of(&val)) {
+                    Ok(dt) => dt.format(format).to_string(),
+                    // Leave unrecognized time as-is.
+                    Err(_) => return Some(val),
+                }
+            },
+            Filter::Truncate(len) => {
+                let text = text_of(&val);
+                if text.chars().count() > len {
+                    // The ellipsis counts as a character.
+                    let mut truncated: String = text.chars()
+                        .take(len.saturating_sub(1))
+                        .collect();
+                    truncated = truncated.trim_end().to_owned();
+                    if len > 0 { truncated.push('…'); }
+                    truncated
+                } else {
+                    text
+                }
+            },
+            Filter::Upper => text_of(&val).to_uppercase(),
+            Filter::Lower => text_of(&val).to_lowercase(),
+            Filter::UrlEncode => encode_url_component(&text_of(&val)),
+            Filter::Default(_) => unreachable!(),
+        };
+        Some(Cow::Owned(Value::Text(filtered)))
+    }
+}
+
+/// Split arguments of a processing instruction by whitespaces. Pipes outside
+/// quotes are returned as individual pieces. Quotes are kept in the pieces.
+/// None is returned if a quote is not closed.
+fn split_args(text: &str) -> Option<Vec<&str>> {
+    let mut pieces = Vec::new();
+    let mut beg = None;
+    let mut in_quote = false;
+    let mut escaped = false;
+    for (idx, ch) in text.char_indices() {
+        if in_quote {
+            if escaped { escaped = false; }
+            else if ch == '\\' { escaped = true; }
+            else if ch == '"' { in_quote = false; }
+            continue;
+        }
+        if ch.is_whitespace() || ch == '|' {
+            if let Some(b) = beg.take() { pieces.push(&text[b..idx]); }
+            if ch == '|' { pieces.push("|"); }
+        } else {
+            if beg.is_none() { beg = Some(idx); }
+            if ch == '"' { in_quote = true; }
+        }
+    }
+    if in_quote { return None; }
+    if let Some(b) = beg { pieces.push(&text[b..]); }
+    Some(pieces)
+}
+/// Remove surrounding quotes and unescape `\"` and `\\`. Unquoted text is
+/// returned as-is.
+fn unquote(text: &str) -> String {
+    if text.len() < 2 || !text.starts_with('"') || !text.ends_with('"') {
+        return text.to_owned();
+    }
+    let mut rv = String::new();
+    let mut escaped = false;
+    for ch in text[1..(text.len() - 1)].chars() {
+        if !escaped && ch == '\\' {
+            escaped = true;
+        } else {
+            rv.push(ch);
+            escaped = false;
+        }
+    }
+    rv
+}
+/// Value of a named argument passed to fragment.
+enum FragmentArg {
+    /// `name="text"` or `name=text`.
+    Literal(String),
+    /// `name=var:variable`.
+    Variable(String),
+}
+/// Parse fragment path and named arguments, like
+/// `card.html title=var:title href="/about/"`. None is returned if there is
+/// any syntax error.
+fn parse_fragment(arg: &str) -> Option<(String, Vec<(String, FragmentArg)>)> {
+    let pieces = split_args(arg)?;
+    let (path, pieces) = pieces.split_first()?;
+    let mut args = Vec::new();
+    for piece in pieces {
+        let mut key_n_val = piece.splitn(2, '=');
+        let key = key_n_val.next().unwrap_or_default();
+        let val = key_n_val.next()?;
+        if key.is_empty() || key.starts_with('"') { return None; }
+        let val = if val.starts_with("var:") {
+            FragmentArg::Variable(val[4..].to_owned())
+        } else {
+            FragmentArg::Literal(unquote(val))
+        };
+        args.push((key.to_owned(), val));
+    }
+    Some((unquote(path), args))
+}
+/// Parse variable name and filters, like `name | filter arg | filter`. None
+/// is returned if there is any syntax error.
+fn parse_filtered(arg: &str) -> Option<(String, Vec<Filter>)> {
+    let pieces = split_args(arg)?;
+    let mut groups = pieces.split(|piece| *piece == "|");
+    let var_name = match groups.next() {
+        Some(&[name]) => name.to_owned(),
+        _ => return None,
+    };
+    let mut filters = Vec::new();
+    for group in groups {
+        let (name, args) = group.split_first()?;
+        let args: Vec<String> = args.iter().map(|arg| unquote(arg)).collect();
+        filters.push(Filter::parse(name, &args)?);
+    }
+    Some((var_name, filters))
+}
+
+/// How a variable is escaped on insertion.
+#[derive(Clone, Copy)]
+enum Escape {
+    /// `<?var name?>`. Escaped for HTML unless the value is pre-rendered HTML.
+    Html,
+    /// `<?url name?>`. Escaped as URL in attribute values.
+    Url,
+    /// `<?raw name?>`. Inserted as-is.
+    Raw,
+}
+
+/// Parsed piece of template.
+enum Node {
+    /// Plain text copied as-is.
+    Text(String),
+    /// `<?frag path [name=value ...]?>`.
+    Fragment {
+        path: String,
+        args: Vec<(String, FragmentArg)>,
+        location: Location,
+    },
+    /// `<?var name?>`, `<?url name?>` or `<?raw name?>`, optionally followed
+    /// by filters.
+    Variable {
+        var_name: String,
+        filters: Vec<Filter>,
+        escape: Escape,
+    },
+    /// `<?if [not] name?> ... [<?else?> ...] <?endif?>`.
+    Condition {
+        var_name: String,
+        negated: bool,
+        then_nodes: Vec<Node>,
+        else_nodes: Vec<Node>,
+    },
+    /// `<?for item in list?> ... <?endfor?>`.
+    Loop {
+        item_name: String,
+        list_name: String,
+        body: Vec<Node>,
+    },
+    /// `<?extends "layout.html"?>`.
+    Extends(String, Location),
+    /// `<?block name?> ... <?endblock?>`.
+    Block {
+        name: String,
+        body: Vec<Node>,
+    },
+}
+
+/// Overriding blocks indexed by name.
+type Blocks<'a> = BTreeMap<String, &'a [Node]>;
+
+/// Get path of the layout extended by a template.
+fn find_extends(nodes: &[Node]) -> Option<(String, Location)> {
+    nodes.iter()
+        .filter_map(|node| match *node {
+            Node::Extends(ref path, ref location) =>
+                Some((path.to_owned(), location.clone())),
+            _ => None,
+        })
+        .next()
+}
+/// Collect blocks defined in template, including nested ones. Blocks already
+/// collected are not replaced.
+fn collect_blocks<'a>(nodes: &'a [Node], blocks: &mut Blocks<'a>) {
+    for node in nodes {
+        match *node {
+            Node::Block { ref name, ref body } => {
+                blocks.entry(name.to_owned()).or_insert(body);
+                collect_blocks(body, blocks);
+            },
+            Node::Condition { ref then_nodes, ref else_nodes, .. } => {
+                collect_blocks(then_nodes, blocks);
+                collect_blocks(else_nodes, blocks);
+            },
+            Node::Loop { ref body, .. } => collect_blocks(body, blocks),
+            _ => {},
+        }
+    }
+}
+
+/// Lexical piece of template.
+enum Token<'a> {
+    Text(&'a str),
+    /// Processing instruction with `<?` and `?>` stripped, and where it
+    /// begins.
+    Instruction(&'a str, Location),
+}
+
+/// Split template into text and processing instructions.
+fn tokenize<'a>(path: &str, template: &'a str)
+    -> Result<Vec<Token<'a>>, TemplateError> {
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut line_beg = 0;
+    let mut scanned = 0;
+    let mut rest = template;
+    loop {
+        let beg = match rest.find("<?") {
+            Some(beg) => beg,
+            // No more processing instructions, get out of the loop.
+            None => {
+                if !rest.is_empty() { tokens.push(Token::Text(rest)); }
+                return Ok(tokens);
+            },
+        };
+        if beg > 0 { tokens.push(Token::Text(&rest[..beg])); }
+        // Locate the instruction.
+        let offset = template.len() - rest.len() + beg;
+        for (idx, byte) in template[scanned..offset].bytes().enumerate() {
+            if byte == b'\n' {
+                line += 1;
+                line_beg = scanned + idx + 1;
+            }
+        }
+        scanned = offset;
+        let location = Location {
+            path: path.to_owned(),
+            line: line,
+            column: template[line_beg..offset].chars().count() + 1,
+        };
+        rest = &rest[(beg + 2)..];
+        match rest.find("?>") {
+            Some(end) => {
+                tokens.push(Token::Instruction(rest[..end].trim(), location));
+                rest = &rest[(end + 2)..];
+            },
+            None => return Err(TemplateError::at(
+                TemplateErrorKind::UnterminatedTag, &location)),
+        }
+    }
+}
+
+/// Parse nodes until one of the directives in `ends` is met. The directive
+/// that stopped parsing is returned along with the nodes. Reaching the end of
+/// template while `ends` is not empty is an error, which is left unlocated for
+/// the opening instruction to locate.
+fn parse_nodes<'a, I>(tokens: &mut I, ends: &[&str])
+    -> Result<(Vec<Node>, &'a str), TemplateError>
+    where I: Iterator<Item = Token<'a>> {
+    use self::TemplateErrorKind::*;
+
+    let mut nodes = Vec::new();
+    while let Some(token) = tokens.next() {
+        let (instruction, location) = match token {
+            Token::Text(text) => {
+                nodes.push(Node::Text(text.to_owned()));
+                continue;
+            },
+            Token::Instruction(instruction, location) =>
+                (instruction, location),
+        };
+        let invalid_syntax = || TemplateError::at(
+            InvalidSyntax(instruction.to_owned()), &location);
+        let mut parts = instruction.splitn(2, char::is_whitespace);
+        let directive = parts.next().unwrap_or_default();
+        let arg = parts.next().unwrap_or_default().trim();
+        if ends.contains(&directive) {
+            return Ok((nodes, directive));
+        }
+        match directive {
+            "frag" => {
+                let (path, args) = parse_fragment(arg)
+                    .ok_or_else(&invalid_syntax)?;
+                nodes.push(Node::Fragment {
+                    path: path,
+                    args: args,
+                    location: location,
+                });
+            },
+            "var" | "url" | "raw" => {
+                let (var_name, filters) = parse_filtered(arg)
+                    .ok_or_else(&invalid_syntax)?;
+                nodes.push(Node::Variable {
+                    var_name: var_name,
+                    filters: filters,
+                    escape: match directive {
+                        "var" => Escape::Html,
+                        "url" => Escape::Url,
+                        _ => Escape::Raw,
+                    },
+                });
+            },
+            "if" => {
+                let words: Vec<&str> = arg.split_whitespace().collect();
+                let (negated, var_name) = match words.len() {
+                    1 if words[0] != "not" => (false, words[0]),
+                    2 if words[0] == "not" => (true, words[1]),
+                    _ => return Err(invalid_syntax()),
+                };
+                let (then_nodes, end) =
+                    parse_nodes(tokens, &["else", "endif"])
+                        .map_err(|err| err.or_at(&location))?;
+                let else_nodes = if end == "else" {
+                    parse_nodes(tokens, &["endif"])
+                        .map_err(|err| err.or_at(&location))?.0
+                } else {
+                    Vec::new()
+                };
+                nodes.push(Node::Condition {
+                    var_name: var_name.to_owned(),
+                    negated: negated,
+                    then_nodes: then_nodes,
+                    else_nodes: else_nodes,
+                });
+            },
+            "for" => {
+                let words: Vec<&str> = arg.split_whitespace().collect();
+                if words.len() != 3 || words[1] != "in" {
+                    return Err(invalid_syntax());
+                }
+                let (body, _) = parse_nodes(tokens, &["endfor"])
+                    .map_err(|err| err.or_at(&location))?;
+                nodes.push(Node::Loop {
+                    item_name: words[0].to_owned(),
+                    list_name: words[2].to_owned(),
+                    body: body,
+                });
+            },
+            "extends" => {
+                let path = unquote(arg);
+                if path.is_empty() {
+                    return Err(invalid_syntax());
+                }
+                nodes.push(Node::Extends(path, location));
+            },
+            "block" => {
+                if arg.is_empty() || arg.contains(char::is_whitespace) {
+                    return Err(invalid_syntax());
+                }
+                let (body, _) = parse_nodes(tokens, &["endblock"])
+                    .map_err(|err| err.or_at(&location))?;
+                nodes.push(Node::Block {
+                    name: arg.to_owned(),
+                    body: body,
+                });
+            },
+            "else" | "endif" | "endfor" | "endblock" =>
+                return Err(TemplateError::at(
+                    UnbalancedBlock(directive.to_owned()), &location)),
+            _ => return Err(TemplateError::at(
+                UnknownDirective(directive.to_owned()), &location)),
+        }
+    }
+    match ends.last() {
+        Some(end) => Err(TemplateError::new(UnclosedBlock((*end).to_owned()))),
+        None => Ok((nodes, "")),
+    }
+}
+
+/// Template compiled from text.
+pub struct Template {
+    /// Template path in $TEMPLATE_DIR.
+    path: String,
+    nodes: Vec<Node>,
+}
+impl Template {
+    /// Compile template. `path` is used to locate errors.
+    pub fn parse(path: &str, template: &str) -> Result<Template, TemplateError> {
+        let tokens = tokenize(path, template)?;
+        let (nodes, _) = parse_nodes(&mut tokens.into_iter(), &[])?;
+        Ok(Template {
+            path: path.to_owned(),
+            nodes: nodes,
+        })
+    }
+}
+
+lazy_static! {
+    /// Compiled templates indexed by local path, along with the modification
+    /// time of the file when it was compiled.
+    static ref COMPILED_TEMPLATES:
+        RwLock<HashMap<PathBuf, (SystemTime, Arc<Template>)>> =
+        RwLock::new(HashMap::new());
+}
+
+/// Load compiled template from template directory. Templates are compiled
+/// once, and are compiled again only when the files are modified.
+pub fn load_template(rel_path: &Path) -> Result<Arc<Template>, TemplateError> {
+    let path = path_buf![&CONFIGS.template_dir, rel_path];
+    let rel_path = rel_path.to_string_lossy();
+    let missing = || TemplateError::new(
+        TemplateErrorKind::MissingTemplate(rel_path.clone().into_owned()));
+    let modified = match metadata(&path).and_then(|meta| meta.modified()) {
+        Ok(modified) => modified,
+        Err(_) => {
+            if let Ok(mut locked) = COMPILED_TEMPLATES.write() {
+                locked.remove(&path);
+            }
+            return Err(missing());
+        },
+    };
+    if let Ok(locked) = COMPILED_TEMPLATES.read() {
+        if let Some(&(compiled_time, ref template)) = locked.get(&path) {
+            if compiled_time == modified {
+                return Ok(template.clone());
+            }
+        }
+    }
+    debug!("Compiling template: {:?}", path);
+    let text = resource::load_text_resource(&path).ok_or_else(missing)?;
+    let template = Arc::new(Template::parse(&rel_path, &text)?);
+    match COMPILED_TEMPLATES.write() {
+        Ok(mut locked) => {
+            locked.insert(path, (modified, template.clone()));
+        },
+        Err(_) => error!("Unable to write-lock."),
+    }
+    Ok(template)
+}
+
+/// Collect paths of templates the given template depends on through fragments
+/// and layouts, including itself. Templates in both branches of conditions are
+/// collected. Missing templates are collected as well, so that their creation
+/// can be noticed.
+pub fn collect_dependencies(rel_path: &Path) -> Vec<PathBuf> {
+    fn collect_nodes(nodes: &[Node], deps: &mut Vec<PathBuf>) {
+        for node in nodes {
+            match *node {
+                Node::Fragment { ref path, .. } |
+                Node::Extends(ref path, _) =>
+                    collect_template(Path::new(path), deps),
+                Node::Condition { ref then_nodes, ref else_nodes, .. } => {
+                    collect_nodes(then_nodes, deps);
+                    collect_nodes(else_nodes, deps);
+                },
+                Node::Loop { ref body, .. } |
+                Node::Block { ref body, .. } => collect_nodes(body, deps),
+                _ => {},
+            }
+        }
+    }
+    fn collect_template(rel_path: &Path, deps: &mut Vec<PathBuf>) {
+        let path = path_buf![&CONFIGS.template_dir, rel_path];
+        // Visited already. It also breaks cyclic inclusion.
+        if deps.contains(&path) { return; }
+        deps.push(path);
+        if let Ok(template) = load_template(rel_path) {
+            collect_nodes(&template.nodes, deps);
+        }
+    }
+    let mut deps = Vec::new();
+    collect_template(rel_path, &mut deps);
+    deps
+}
+
+/// Maximum number of fragments and layouts being filled at the same time.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// States of filling a template.
+struct Context {
+    /// Variables bound by `for` loops and fragment arguments, the innermost is
+    /// the last.
+    locals: Vec<(String, Value)>,
+    /// Paths of templates being filled, the innermost is the last.
+    includes: Vec<String>,
+}
+impl Context {
+    fn new() -> Context {
+        Context {
+            locals: Vec::new(),
+            includes: Vec::new(),
+        }
+    }
+    /// Mark a template as being filled. Cyclic inclusion and inclusion nested
+    /// too deeply are refused.
+    fn enter(&mut self, path: &str) -> Result<(), TemplateErrorKind> {
+        if let Some(pos) = self.includes.iter().position(|p| p == path) {
+            let mut chain = self.includes[pos..].to_vec();
+            chain.push(path.to_owned());
+            return Err(TemplateErrorKind::IncludeCycle(chain));
+        }
+        if self.includes.len() >= MAX_INCLUDE_DEPTH {
+            let mut chain = self.includes.clone();
+            chain.push(path.to_owned());
+            return Err(TemplateErrorKind::IncludeTooDeep(chain));
+        }
+        self.includes.push(path.to_owned());
+        Ok(())
+    }
+}
+
 pub struct TemplateVariables {
-    vars: BTreeMap<String, String>,
+    vars: BTreeMap<String, Value>,
 }
 
 impl TemplateVariables {
@@ -28,63 +878,192 @@ impl TemplateVariables {
             None => return,
         };
         for (key, val) in metadata.iter() {
-            self.insert(key.to_owned(), val.as_str().unwrap().to_owned());
+            self.insert(key.to_owned(), Value::from(val));
         }
     }
 
-    fn get_fragment(&self, rel_path: &Path) -> Option<String> {
-        let path = path_buf![&CONFIGS.template_dir, rel_path];
-        resource::load_text_resource(path.as_path())
-            .and_then(|s| self.fill_template(&s))
+    /// Read variables from front matter of `content.md`. Variables defined in
+    /// front matter take precedence over the ones in `metadata.json`, so it
+    /// should be read after `metadata.json`.
+    pub fn read_from_front_matter(&mut self, local_path: &Path) {
+        let content = match resource::load_article_text(local_path) {
+            Some(s) => s,
+            None => return,
+        };
+        if let (Some(front_matter), _) = split_front_matter(&content) {
+            self.vars.extend(front_matter);
+        }
+    }
+
+    fn get_fragment(&self, rel_path: &Path, location: &Location,
+        ctx: &mut Context, rv: &mut String) -> Result<(), TemplateError> {
+        let template = load_template(rel_path)
+            .map_err(|err| err.or_at(location))?;
+        self.render_template(&template, Some(location), ctx, rv)
     }
 
-    pub fn fill_template(&self, template: &str) -> Option<String> {
+    pub fn fill_template(&self, template: &Template)
+        -> Result<String, TemplateError> {
         debug!("Filling template.");
-        let mut template = template.to_owned();
         let mut rv = String::new();
-        loop {
-            match template.find("<?") {
-                Some(beg) => rv.extend(template.drain(..beg)),
-                // No more processing instructions, get out of the loop.
-                None => return Some(rv + &template),
-            }
-            match template.find("?>") {
-                Some(end) => {
-                    if end < 2 {
-                        // Tag beginning and ending overlaps.
-                        return None;
+        self.render_template(template, None, &mut Context::new(), &mut rv)?;
+        Ok(rv)
+    }
+
+    /// Fill compiled template. If the template extends a layout, the layout is
+    /// filled instead, with blocks overridden by the ones defined in the
+    /// template. Layouts can extend other layouts as well. `location` is where
+    /// the template is included, if it's a fragment.
+    fn render_template(&self, template: &Template, location: Option<&Location>,
+        ctx: &mut Context, rv: &mut String) -> Result<(), TemplateError> {
+        let depth = ctx.includes.len();
+        let res = self.render_template_in(template, location, ctx, rv);
+        ctx.includes.truncate(depth);
+        res
+    }
+    fn render_template_in(&self, template: &Template,
+        location: Option<&Location>, ctx: &mut Context, rv: &mut String)
+        -> Result<(), TemplateError> {
+        if let Err(kind) = ctx.enter(&template.path) {
+            return Err(match location {
+                Some(location) => TemplateError::at(kind, location),
+                None => TemplateError::new(kind),
+            });
+        }
+        let mut layouts: Vec<Arc<Template>> = Vec::new();
+        let mut layout_path = find_extends(&template.nodes);
+        while let Some((path, location)) = layout_path {
+            debug!("Extend layout: {}", path);
+            ctx.enter(&path)
+                .map_err(|kind| TemplateError::at(kind, &location))?;
+            let layout = load_template(Path::new(&path))
+                .map_err(|err| err.or_at(&location))?;
+            layout_path = find_extends(&layout.nodes);
+            layouts.push(layout);
+        }
+        // Blocks defined in derived templates take precedence.
+        let mut blocks = Blocks::new();
+        collect_blocks(&template.nodes, &mut blocks);
+        for layout in layouts.iter() {
+            collect_blocks(&layout.nodes, &mut blocks);
+        }
+        let root = layouts.last().map_or(&template.nodes, |layout| &layout.nodes);
+        self.render_nodes(root, &blocks, ctx, rv)
+    }
+
+    /// Look up variable by name. Loop variables shadow template variables.
+    /// Fields of objects are accessed with dots, like `article.title`.
+    fn lookup<'a>(&'a self, ctx: &'a Context, name: &str)
+        -> Option<&'a Value> {
+        let mut segs = name.split('.');
+        let root = segs.next().unwrap_or_default();
+        let mut val = match ctx.locals.iter().rev()
+            .find(|&&(ref k, _)| k == root) {
+            Some(&(_, ref val)) => val,
+            None => self.vars.get(root)?,
+        };
+        for seg in segs {
+            val = val.field(seg)?;
+        }
+        Some(val)
+    }
+
+    fn render_nodes<'a>(&self, nodes: &'a [Node], blocks: &Blocks<'a>,
+        ctx: &mut Context, rv: &mut String) -> Result<(), TemplateError> {
+        for node in nodes {
+            match *node {
+                Node::Text(ref text) => rv.push_str(text),
+                Node::Fragment { ref path, ref args, ref location } => {
+                    debug!("Inline fragment: {}", path);
+                    // Arguments are evaluated in the scope of the caller, and
+                    // are visible only in the fragment.
+                    let mut bound = Vec::with_capacity(args.len());
+                    for &(ref name, ref arg) in args {
+                        let val = match *arg {
+                            FragmentArg::Literal(ref text) =>
+                                Value::from(text.as_str()),
+                            FragmentArg::Variable(ref var_name) =>
+                                self.lookup(ctx, var_name)
+                                    .cloned()
+                                    .unwrap_or_else(|| Value::from("")),
+                        };
+                        bound.push((name.to_owned(), val));
                     }
-                    {
-                        let parts: Vec<&str> = template[2..end].splitn(2, ' ')
-                            .collect();
-                        if parts[0] == "frag" {
-                            // Insert fragment.
-                            let frag_path = parts[1].trim();
-                            debug!("Inline fragment: {}", frag_path);
-                            rv += &self.get_fragment(Path::new(frag_path))
-                                .unwrap_or_default();
-                        } else if parts[0] == "var" {
-                            // Insert variable.
-                            let var_name = parts[1].trim();
-                            debug!("Insert variable: {}", var_name);
-                            if let Some(st) = self.get(var_name) {
-                                rv += st;
-                            }
+                    let outer = mem::replace(&mut ctx.locals, bound);
+                    let res = self.get_fragment(Path::new(path), location,
+                        ctx, rv);
+                    ctx.locals = outer;
+                    res?;
+                },
+                Node::Variable { ref var_name, ref filters, escape } => {
+                    debug!("Insert variable: {}", var_name);
+                    let val = filters.iter().fold(
+                        self.lookup(ctx, var_name).map(Cow::Borrowed),
+                        |val, filter| filter.apply(val));
+                    if let Some(val) = val {
+                        match (escape, &*val) {
+                            (Escape::Html, &Value::Html(ref html)) =>
+                                *rv += html,
+                            (Escape::Html, _) =>
+                                *rv += &escape_html(&val.to_string()),
+                            (Escape::Url, _) =>
+                                *rv += &escape_url(&val.to_string()),
+                            (Escape::Raw, _) => *rv += &val.to_string(),
                         }
                     }
-                    // Ignore unknown processing instructions.
-                    template.drain(..(end + 2));
                 },
-                None => return None,
+                Node::Condition { ref var_name, negated, ref then_nodes,
+                    ref else_nodes } => {
+                    let is_set = self.lookup(ctx, var_name)
+                        .map_or(false, Value::is_set);
+                    if is_set != negated {
+                        self.render_nodes(then_nodes, blocks, ctx, rv)?;
+                    } else {
+                        self.render_nodes(else_nodes, blocks, ctx, rv)?;
+                    }
+                },
+                Node::Loop { ref item_name, ref list_name, ref body } => {
+                    let list = match self.lookup(ctx, list_name) {
+                        Some(&Value::List(ref list)) => list.clone(),
+                        // Absent variables and non-lists are iterated as
+                        // empty lists.
+                        _ => continue,
+                    };
+                    let len = list.len();
+                    for (idx, item) in list.into_iter().enumerate() {
+                        // Provide loop states in `loop`.
+                        let mut loop_state = BTreeMap::new();
+                        loop_state.insert("index".to_owned(),
+                            Value::Number((idx + 1) as f64));
+                        loop_state.insert("first".to_owned(),
+                            Value::from(idx == 0));
+                        loop_state.insert("last".to_owned(),
+                            Value::from(idx + 1 == len));
+                        ctx.locals.push(("loop".to_owned(),
+                            Value::Object(loop_state)));
+                        ctx.locals.push((item_name.to_owned(), item));
+                        let res = self.render_nodes(body, blocks, ctx, rv);
+                        ctx.locals.pop();
+                        ctx.locals.pop();
+                        res?;
+                    }
+                },
+                // Layout has been resolved in `render_template`.
+                Node::Extends(..) => {},
+                Node::Block { ref name, ref body } => {
+                    let body = blocks.get(name).map_or(&body[..], |b| *b);
+                    self.render_nodes(body, blocks, ctx, rv)?;
+                },
             }
         }
+        Ok(())
     }
 
     /// Complete template variable map with default value.
     pub fn complete_with_default(&mut self, local_path: &Path) {
         fn get_meta_dt<F: FnOnce(&Metadata) -> io::Result<SystemTime>>(local_path: &Path, dt_fn: F)
             -> Option<String> {
-            let path = path_buf![local_path, "content.md"];
+            let path = resource::locate_article_content(local_path)?;
             match metadata(path) {
                 Ok(file_meta) => match (dt_fn)(&file_meta) {
                     Ok(sys_time) => Some(chrono::DateTime::<Utc>::from(sys_time)
@@ -130,15 +1109,336 @@ impl TemplateVariables {
         self.vars.contains_key(key)
     }
     #[inline]
-    pub fn insert(&mut self, key: String, value: String) -> Option<String> {
-        self.vars.insert(key, value)
+    pub fn insert<V: Into<Value>>(&mut self, key: String, value: V)
+        -> Option<Value> {
+        self.vars.insert(key, value.into())
     }
     #[inline]
-    pub fn get(&self, key: &str) -> Option<&String> {
+    pub fn get(&self, key: &str) -> Option<&Value> {
         self.vars.get(key)
     }
     #[inline]
-    pub fn remove(&mut self, key: &str) -> Option<String> {
+    pub fn remove(&mut self, key: &str) -> Option<Value> {
         self.vars.remove(key)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::io::Write;
+
+    use super::*;
+
+    fn fill(vars: &TemplateVariables, template: &str)
+        -> Result<String, TemplateError> {
+        vars.fill_template(&Template::parse("test.html", template)?)
+    }
+    fn vars(pairs: &[(&str, &str)]) -> TemplateVariables {
+        let mut rv = TemplateVariables::new();
+        for &(key, val) in pairs {
+            rv.insert(key.to_owned(), val);
+        }
+        rv
+    }
+    /// Write template file into template directory.
+    fn write_template(rel_path: &str, text: &str) {
+        let path = path_buf![&CONFIGS.template_dir, rel_path];
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        File::create(&path).unwrap().write_all(text.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn condition() {
+        let v = vars(&[("set", "x"), ("empty", "")]);
+        assert_eq!(fill(&v, "<?if set?>A<?else?>B<?endif?>").unwrap(), "A");
+        assert_eq!(fill(&v, "<?if empty?>A<?else?>B<?endif?>").unwrap(), "B");
+        assert_eq!(fill(&v, "<?if absent?>A<?endif?>").unwrap(), "");
+        assert_eq!(fill(&v, "<?if not empty?>N<?endif?>").unwrap(), "N");
+        assert_eq!(fill(&v, "<?if not set?>N<?endif?>").unwrap(), "");
+    }
+    #[test]
+    fn nested_condition() {
+        let v = vars(&[("set", "x")]);
+        assert_eq!(fill(&v, "<?if absent?>A<?else?><?if set?>B<?endif?>\
+            <?endif?>").unwrap(), "B");
+    }
+    #[test]
+    fn loop_over_list() {
+        let mut v = vars(&[("set", "x"), ("tag", "outer")]);
+        v.insert("tags".to_owned(), vec![Value::from("a"), Value::from("b")]);
+        assert_eq!(fill(&v, "<?for tag in tags?><?if set?><?var tag?>\
+            <?endif?><?if not loop.last?>,<?endif?><?endfor?>").unwrap(),
+            "a,b");
+        assert_eq!(fill(&v, "<?for tag in tags?><?var loop.index?>\
+            <?if loop.first?>!<?endif?><?endfor?>").unwrap(), "1!2");
+        assert_eq!(fill(&v, "<?for a in tags?><?for b in tags?><?var a?>\
+            <?var b?> <?endfor?><?endfor?>").unwrap(), "aa ab ba bb ");
+        // Loop variables are gone after the loop.
+        assert_eq!(fill(&v, "<?for tag in tags?><?endfor?><?var tag?>\
+            <?var loop.index?>").unwrap(), "outer");
+        // Absent variables and non-lists are iterated as empty lists.
+        assert_eq!(fill(&v, "<?for tag in absent?>x<?endfor?>").unwrap(), "");
+        assert_eq!(fill(&v, "<?for c in set?>x<?endfor?>").unwrap(), "");
+        assert!(Template::parse("test.html", "<?for x xs?><?endfor?>")
+            .is_err());
+    }
+    #[test]
+    fn invalid_syntax() {
+        for template in &["<?if?><?endif?>", "<?if not?><?endif?>",
+            "<?if a b?><?endif?>", "<?for x xs?><?endfor?>",
+            "<?block?><?endblock?>", "<?var?>", "<?var a b?>",
+            "<?var \"a?>"] {
+            match Template::parse("test.html", template) {
+                Err(TemplateError {
+                    kind: TemplateErrorKind::InvalidSyntax(_), ..
+                }) => {},
+                _ => panic!("`{}` should be invalid", template),
+            }
+        }
+    }
+    #[test]
+    fn unbalanced_and_unclosed_blocks() {
+        let err = Template::parse("test.html", "<?endif?>").err().unwrap();
+        match err.kind {
+            TemplateErrorKind::UnbalancedBlock(ref directive) =>
+                assert_eq!(directive, "endif"),
+            _ => panic!("unexpected error: {}", err),
+        }
+        let err = Template::parse("test.html", "<?for a in b?><?if x?>")
+            .err().unwrap();
+        match err.kind {
+            TemplateErrorKind::UnclosedBlock(ref directive) =>
+                assert_eq!(directive, "endif"),
+            _ => panic!("unexpected error: {}", err),
+        }
+    }
+    #[test]
+    fn escape() {
+        let mut v = vars(&[
+            ("text", "<a href='x'>&"),
+            ("url", "/a b?x=1&y=\"2\""),
+            ("js", " JavaScript:alert(1)"),
+            ("data", "data:text/html,<script>"),
+            ("relative", "notes:1/"),
+        ]);
+        v.insert("html".to_owned(), Value::Html("<b>".to_owned()));
+        assert_eq!(fill(&v, "<?var text?>").unwrap(),
+            "&lt;a href=&#39;x&#39;&gt;&amp;");
+        assert_eq!(fill(&v, "<?raw text?>").unwrap(), "<a href='x'>&");
+        assert_eq!(fill(&v, "<?var html?>").unwrap(), "<b>");
+        assert_eq!(fill(&v, "<?url url?>").unwrap(),
+            "/a%20b?x=1&amp;y=%222%22");
+        assert_eq!(fill(&v, "<?url js?>").unwrap(), "#");
+        assert_eq!(fill(&v, "<?url data?>").unwrap(), "#");
+        assert_eq!(fill(&v, "<?url relative?>").unwrap(), "notes:1/");
+    }
+    #[test]
+    fn filter_chain() {
+        let mut v = vars(&[
+            ("published", "2017-09-03T10:00:00+08:00"),
+            ("title", "Hello World <x>"),
+        ]);
+        v.insert("html".to_owned(),
+            Value::Html("<p>a &amp; b</p>".to_owned()));
+        assert_eq!(fill(&v, "<?var published | date \"%Y-%m-%d\"?>")
+            .unwrap(), "2017-09-03");
+        assert_eq!(fill(&v, "<?var title|upper?>").unwrap(),
+            "HELLO WORLD &lt;X&gt;");
+        assert_eq!(fill(&v, "<?var title | lower | urlencode?>").unwrap(),
+            "hello%20world%20%3Cx%3E");
+        assert_eq!(fill(&v, "<?var html | upper?>").unwrap(), "A &amp; B");
+        assert_eq!(fill(&v, "<?var absent | default \"a \\\"q\\\"\" | upper?>")
+            .unwrap(), "A &quot;Q&quot;");
+        assert_eq!(fill(&v, "<?var absent | upper?>").unwrap(), "");
+        assert_eq!(fill(&v, "<?var title | truncate 7?>").unwrap(),
+            "Hello…");
+        assert_eq!(fill(&v, "<?var title | truncate 15?>").unwrap(),
+            "Hello World &lt;x&gt;");
+        assert_eq!(fill(&v, "<?var title | truncate 14?>").unwrap(),
+            "Hello World &lt;…");
+        assert_eq!(fill(&v, "<?var html | truncate 3?>").unwrap(), "a…");
+        assert_eq!(fill(&v, "<?var title | truncate 0?>").unwrap(), "");
+        for template in &["<?var title | bogus?>",
+            "<?var title | date \"%Q\"?>", "<?var title | truncate x?>",
+            "<?var title | upper 1?>", "<?var title |?>"] {
+            assert!(Template::parse("test.html", template).is_err(),
+                "`{}` should be invalid", template);
+        }
+    }
+    #[test]
+    fn layout() {
+        write_template("layout/base.html",
+            "<h1><?block title?>Untitled<?endblock?></h1>\
+            <?block body?><?endblock?>");
+        write_template("layout/page.html",
+            "<?extends \"layout/base.html\"?>\
+            <?block body?><main><?block main?>M<?endblock?></main>\
+            <?endblock?>");
+        let v = vars(&[("title", "Hi")]);
+        // Blocks of derived templates override the ones of layouts.
+        assert_eq!(fill(&v, "<?extends \"layout/base.html\"?>\
+            <?block title?><?var title?><?endblock?>").unwrap(),
+            "<h1>Hi</h1>");
+        // Default content of layouts is used if blocks are not overridden.
+        // Text outside blocks is ignored.
+        assert_eq!(fill(&v, "<?extends \"layout/base.html\"?>ignored")
+            .unwrap(), "<h1>Untitled</h1>");
+        // Layouts can extend other layouts.
+        assert_eq!(fill(&v, "<?extends \"layout/page.html\"?>\
+            <?block main?>X<?endblock?>").unwrap(),
+            "<h1>Untitled</h1><main>X</main>");
+        assert_eq!(fill(&v, "<?extends \"layout/page.html\"?>\
+            <?block title?>T<?endblock?>").unwrap(),
+            "<h1>T</h1><main>M</main>");
+        let err = fill(&v, "\n<?extends \"layout/missing.html\"?>")
+            .err().unwrap();
+        match err.kind {
+            TemplateErrorKind::MissingTemplate(ref path) =>
+                assert_eq!(path, "layout/missing.html"),
+            _ => panic!("unexpected error: {}", err),
+        }
+        assert!(err.to_string().starts_with("test.html:2:1: "));
+    }
+    #[test]
+    fn args() {
+        assert_eq!(split_args("a | b \"c | d\"|e").unwrap(),
+            vec!["a", "|", "b", "\"c | d\"", "|", "e"]);
+        assert!(split_args("a \"b").is_none());
+        assert_eq!(unquote("\"a \\\"b\\\\\""), "a \"b\\");
+        let (path, args) =
+            parse_fragment("card.html title=var:t href=\"/about us/\"")
+                .unwrap();
+        assert_eq!(path, "card.html");
+        match args[0] {
+            (ref name, FragmentArg::Variable(ref var_name)) =>
+                assert_eq!((name.as_str(), var_name.as_str()), ("title", "t")),
+            _ => panic!("`title` should be a variable"),
+        }
+        match args[1] {
+            (_, FragmentArg::Literal(ref text)) =>
+                assert_eq!(text, "/about us/"),
+            _ => panic!("`href` should be literal"),
+        }
+        assert!(parse_fragment("card.html oops").is_none());
+    }
+    #[test]
+    fn fragment_scope() {
+        write_template("frag/outer.html",
+            "<?var title?>:<?frag frag/inner.html name=var:title?>");
+        write_template("frag/inner.html",
+            "[<?var tag?>|<?var loop.index?>|<?var title?>|<?var name?>\
+            |<?var site?>]");
+        let mut v = vars(&[("site", "S")]);
+        v.insert("tags".to_owned(), vec![Value::from("a")]);
+        // Neither the loop variables of the caller nor the arguments of the
+        // parent fragment are visible in nested fragments. Template variables
+        // are.
+        assert_eq!(fill(&v, "<?for tag in tags?>\
+            <?frag frag/outer.html title=var:tag?><?endfor?>").unwrap(),
+            "a:[|||a|S]");
+        // Arguments are gone after the fragment.
+        assert_eq!(fill(&v, "<?frag frag/outer.html title=\"t\"?>\
+            <?var title?>").unwrap(), "t:[|||t|S]");
+    }
+    #[test]
+    fn compiled_template_cache() {
+        let rel_path = Path::new("compiled/page.html");
+        let path = path_buf![&CONFIGS.template_dir, rel_path];
+        let set_compiled_time = |time: SystemTime| {
+            COMPILED_TEMPLATES.write().unwrap().get_mut(&path).unwrap().0 =
+                time;
+        };
+        let modified = || metadata(&path).unwrap().modified().unwrap();
+        write_template("compiled/page.html", "A");
+        let first = load_template(rel_path).unwrap();
+        assert!(Arc::ptr_eq(&first, &load_template(rel_path).unwrap()));
+        // Templates are not compiled again as long as the modification time
+        // is the same.
+        write_template("compiled/page.html", "B");
+        set_compiled_time(modified());
+        let cached = load_template(rel_path).unwrap();
+        assert!(Arc::ptr_eq(&first, &cached));
+        assert_eq!(TemplateVariables::new().fill_template(&cached).unwrap(),
+            "A");
+        // Modified templates are compiled again.
+        set_compiled_time(::std::time::UNIX_EPOCH);
+        let compiled = load_template(rel_path).unwrap();
+        assert!(!Arc::ptr_eq(&first, &compiled));
+        assert_eq!(TemplateVariables::new().fill_template(&compiled)
+            .unwrap(), "B");
+    }
+    #[test]
+    fn typed_metadata() {
+        let dir = path_buf![&CONFIGS.post_dir, "typed-metadata"];
+        fs::create_dir_all(&dir).unwrap();
+        File::create(path_buf![&dir, "metadata.json"]).unwrap()
+            .write_all(br#"{
+                "draft": true, "hidden": false, "count": 3, "ratio": 1.5,
+                "zero": 0, "tags": ["a", "b"], "empty": [], "nothing": null,
+                "series": { "name": "S", "part": 2, "parts": [{ "n": 1 }] }
+            }"#).unwrap();
+        let mut v = TemplateVariables::new();
+        v.read_from_metadata(&dir);
+        let check = |template, expected| {
+            assert_eq!(fill(&v, template).unwrap(), expected);
+        };
+        check("<?if draft?>D<?endif?><?if hidden?>H<?endif?>", "D");
+        check("<?var draft?> <?var hidden?>", "true false");
+        check("<?var count?> <?var ratio?> <?if zero?>Z<?endif?>", "3 1.5 ");
+        check("<?for tag in tags?><?var tag?>;<?endfor?>", "a;b;");
+        check("<?var tags?> <?var tags.1?>", "a, b b");
+        check("<?if empty?>E<?endif?><?if not nothing?>N<?endif?>", "N");
+        check("<?var nothing?>", "");
+        check("<?var series.name?><?var series.part?><?var series?>", "S2");
+        check("<?var series.parts.0.n?><?var series.absent.x?>", "1");
+    }
+    #[test]
+    fn error_location() {
+        let parse_err = |template| Template::parse("page.html", template)
+            .err().unwrap().to_string();
+        assert_eq!(parse_err("a\nbc <?if x?>\n\n  <?var y"),
+            "page.html:4:3: processing instruction is not terminated with \
+            `?>`");
+        // Unclosed blocks are located at where they are opened.
+        assert_eq!(parse_err("a\nbc <?if x?>\n\n  <?var y?>"),
+            "page.html:2:4: block is not closed with `endif`");
+        assert_eq!(parse_err("<?for a in b?>\n<?block c?><?endfor?>"),
+            "page.html:2:12: `endfor` doesn't match any opened block");
+        assert_eq!(parse_err("<?if a?>\r\n<?else?><?else?>"),
+            "page.html:2:9: `else` doesn't match any opened block");
+        // Columns are counted in characters rather than bytes.
+        assert_eq!(parse_err("é<?bogus?>"),
+            "page.html:1:2: unknown directive `bogus`");
+        assert_eq!(parse_err("\n\t<?for x?><?endfor?>"),
+            "page.html:2:2: invalid syntax in `for x`");
+    }
+    #[test]
+    fn include_cycle_and_depth() {
+        let mut ctx = Context::new();
+        ctx.enter("a.html").unwrap();
+        ctx.enter("b.html").unwrap();
+        match ctx.enter("a.html") {
+            Err(TemplateErrorKind::IncludeCycle(chain)) =>
+                assert_eq!(chain, vec!["a.html", "b.html", "a.html"]),
+            _ => panic!("cyclic inclusion should be refused"),
+        }
+        for depth in 2..MAX_INCLUDE_DEPTH {
+            ctx.enter(&format!("{}.html", depth)).unwrap();
+        }
+        match ctx.enter("last.html") {
+            Err(TemplateErrorKind::IncludeTooDeep(chain)) => {
+                assert_eq!(chain.len(), MAX_INCLUDE_DEPTH + 1);
+                assert_eq!(chain.last().unwrap(), "last.html");
+            },
+            _ => panic!("inclusion nested too deeply should be refused"),
+        }
+        // A layout extending itself is refused before it's loaded.
+        let template =
+            Template::parse("self.html", "<?extends \"self.html\"?>").unwrap();
+        let err = TemplateVariables::new().fill_template(&template)
+            .err().unwrap();
+        assert_eq!(err.to_string(),
+            "self.html:1:1: cyclic inclusion: self.html -> self.html");
+    }
+}
@@ -0,0 +1,80 @@
+use std::sync::{Arc, RwLock};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+use writium::notify::{watcher, DebouncedEvent, RecursiveMode, Watcher};
+
+use writium::settings::CONFIGS;
+use writium::WritiumServer;
+
+/// Check if the event changes sources of pages.
+fn is_change(event: &DebouncedEvent) -> bool {
+    match *event {
+        DebouncedEvent::Create(_) |
+        DebouncedEvent::Write(_) |
+        DebouncedEvent::Remove(_) |
+        DebouncedEvent::Rename(_, _) |
+        DebouncedEvent::Rescan => true,
+        DebouncedEvent::Error(ref err, ref path) => {
+            warn!("Error occurred watching {:?}: {}", path, err);
+            false
+        },
+        // Notices are sent before changes are done. Changes of permissions
+        // don't matter.
+        _ => false,
+    }
+}
+
+/// Wait until changes settle down. Events are consumed until no more event
+/// arrives within the delay.
+///
+/// Returns false if the watcher is gone.
+fn wait_for_changes(rx: &Receiver<DebouncedEvent>, delay: Duration) -> bool {
+    loop {
+        match rx.recv() {
+            Ok(ref event) if is_change(event) => break,
+            Ok(_) => {},
+            Err(_) => return false,
+        }
+    }
+    loop {
+        match rx.recv_timeout(delay) {
+            Ok(_) => {},
+            Err(RecvTimeoutError::Timeout) => return true,
+            Err(RecvTimeoutError::Disconnected) => return false,
+        }
+    }
+}
+
+/// Watch post, template and error directories, and update cache when there
+/// are changes. Only pages whose sources have changed are generated again.
+pub fn spawn_watcher(shared: Arc<RwLock<WritiumServer>>) {
+    let delay = Duration::from_millis(CONFIGS.watch_delay);
+    let (tx, rx) = channel();
+    let mut watcher = match watcher(tx, delay) {
+        Ok(watcher) => watcher,
+        Err(err) => {
+            error!("Unable to create filesystem watcher: {}", err);
+            return;
+        },
+    };
+    for dir in &[&CONFIGS.post_dir, &CONFIGS.template_dir, &CONFIGS.error_dir] {
+        match watcher.watch(dir, RecursiveMode::Recursive) {
+            Ok(_) => info!("Watching directory: {}", dir),
+            Err(err) => error!("Unable to watch directory {}: {}", dir, err),
+        }
+    }
+    thread::spawn(move || {
+        // The watcher stops as soon as it's dropped, so keep it here.
+        let _watcher = watcher;
+        while wait_for_changes(&rx, delay) {
+            info!("Changes detected. Updating cache.");
+            match shared.write() {
+                Ok(mut locked) => (*locked).recache(),
+                Err(_) => error!("Unable to write-lock."),
+            }
+        }
+        warn!("Filesystem watcher stopped.");
+    });
+}